@@ -1,13 +1,83 @@
-use crate::chessboard::{Checkmate, Chessboard};
+use crate::chessboard::Chessboard;
+use crate::eval::{self, Evaluator};
+use crate::eval_params::{self, EvalParams};
+use crate::move_order::{mvv_lva, SearchTables};
 use crate::piece::{Piece, Side};
-use crate::table::{TranspositionTable, TTEntry, Flag};
-use rayon::prelude::*;
+use crate::skill;
+use crate::table::{score_from_tt, score_to_tt, Flag, TTEntry, TTMove, TranspositionTable};
+use std::cmp::Reverse;
 use std::fmt;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 const MAX_SIDE: Side = Side::Light;
 const MIN_SIDE: Side = Side::Dark;
 
+/// The deepest iteration we will ever start, even if no other limit is given.
+const MAX_DEPTH: u8 = 64;
+
+/// The furthest from the root the search may go, including extensions.
+pub const MAX_PLY: usize = 128;
+
+/// How often (in nodes) the clock is checked. `Instant::now` isn't free, so we don't want to do it
+/// at every node.
+const TIME_CHECK_INTERVAL: u64 = 64;
+
+/// If the game clock doesn't tell us how many moves are left until the next time control, assume
+/// there are this many.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Time kept in reserve on the game clock so we don't lose on time to overhead.
+const CLOCK_SAFETY_MARGIN: Duration = Duration::from_millis(50);
+
+/// How many plies past the horizon quiescence search may keep resolving captures.
+const MAX_QUIESCENCE_DEPTH: u8 = 8;
+
+/// Larger than any real score, but small enough that negating it can't overflow.
+const INFINITY: i32 = 2_000_000_000;
+
+/// The score for checkmating on the spot. Being mated `ply` plies from the root scores
+/// `-(MATE - ply)`, so a quicker mate is always preferred, and a slower one when we're the one
+/// being mated.
+pub const MATE: i32 = 100_000;
+
+/// Every score at least this big (or at most minus this) is a mate score
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// Aspiration windows are only used from this depth on. The scores of very shallow searches jump
+/// around too much for a narrow window to be useful.
+const ASPIRATION_MIN_DEPTH: u8 = 4;
+
+/// The initial half-width of the aspiration window around the previous iteration's score. It is
+/// doubled every time the search falls outside of it.
+const ASPIRATION_WINDOW: i32 = 25;
+
+/// Null move pruning is only tried with at least this much depth left.
+const NULL_MOVE_MIN_DEPTH: u8 = 3;
+
+/// Reverse futility pruning is only tried with at most this much depth left.
+const REVERSE_FUTILITY_MAX_DEPTH: u8 = 3;
+
+/// How far the static evaluation must be above beta, per ply of depth left, for reverse futility
+/// pruning to assume the node will fail high.
+const REVERSE_FUTILITY_MARGIN: i32 = 100;
+
+/// Indexed by depth left. If the static evaluation plus this margin is still below alpha, quiet
+/// moves are assumed to be unable to raise it, and are skipped.
+const FUTILITY_MARGINS: [i32; 3] = [0, 200, 400];
+
+/// Late move reductions are only done with at least this much depth left...
+const LMR_MIN_DEPTH: u8 = 3;
+
+/// ...and only for moves after this many have been searched at full depth.
+const LMR_MIN_MOVES: usize = 3;
+
+/// Delta pruning margin. A capture is skipped if winning the captured piece, plus this much, still
+/// wouldn't raise the score to alpha.
+const DELTA_MARGIN: i32 = 200;
+
 // So it seems that using this causes the negamax algorithm to have a double negative
 fn side_sign(side: Side) -> i32 {
     match side {
@@ -16,184 +86,1032 @@ fn side_sign(side: Side) -> i32 {
     }
 }
 
-pub fn get_best_move(chessboard: &Chessboard, depth: u8, tt: Arc<RwLock<TranspositionTable>>) -> (&Piece, [u8; 2]) {
-    //thread::sleep(time::Duration::new(2, 0));
-    let possible_moves = chessboard.possible_moves(chessboard.turn);
-    let scores: Vec<_> = possible_moves
-        .par_iter()
-        .map(|m| {
-            let mut temp = chessboard.clone();
-            temp.try_move(&m.0, m.1, Some(&Piece::Queen));
-            //println!("Considering Move: {}", SimpleMove(*m));
-            // using 2 billion to avoid overflow when negating
-            -negamax_score(
-                &temp,
-                depth - 1,
-                -2_000_000_000,
-                2_000_000_000,
-                vec![SimpleMove(*m)],
-                Arc::clone(&tt),
-            )
-        })
-        .collect();
+/// The state of the clock for the side to move, in the style of a chess clock.
+#[derive(Clone, Copy, Debug)]
+pub struct GameClock {
+    /// Time remaining on the clock.
+    pub time_left: Duration,
+    /// Time added to the clock after every move.
+    pub increment: Duration,
+    /// Moves until the next time control, if there is one.
+    pub moves_to_go: Option<u32>,
+}
 
-    let display: Vec<_> = possible_moves
-        .iter()
-        .map(|a| SimpleMove(*a))
-        .zip(&scores)
-        .collect();
+/// Limits on how long a search may run. Limits that are `None` are ignored, and the search stops
+/// as soon as any of the others is reached (or the stop flag is set).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchLimits {
+    /// The deepest iteration to search
+    pub depth: Option<u8>,
+    /// The maximum number of nodes to visit
+    pub nodes: Option<u64>,
+    /// A fixed amount of time to think for
+    pub movetime: Option<Duration>,
+    /// The game clock, which we use to decide on a time for this move
+    pub clock: Option<GameClock>,
+}
 
-    let tt_guard = tt.read().unwrap();
+impl SearchLimits {
+    /// The most time we may spend on this move, taking both `movetime` and the game clock into
+    /// account.
+    fn time_budget(&self) -> Option<Duration> {
+        let clock_budget = self.clock.map(|clock| {
+            let moves_to_go = clock.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let budget = clock.time_left / moves_to_go + clock.increment * 3 / 4;
+            // never plan to use more than we actually have
+            let available = clock.time_left.checked_sub(CLOCK_SAFETY_MARGIN).unwrap_or_default();
+            budget.min(available)
+        });
 
-    let mut pv = Vec::new();
-    let mut hash = chessboard.zobrist_hash();
+        match (self.movetime, clock_budget) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
 
-    while let Some(entry) = tt_guard.get_for_hash(hash) {
-        if let Some(best_move) = &entry.best_move {
-            pv.push(best_move);
-            hash = Chessboard::update_hash(hash, &best_move);
+/// Switches for the selective parts of the search, so their effect can be measured against plain
+/// alpha-beta. Everything is turned on by default.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    /// Let the opponent move twice in a row, and prune the node if we're still above beta.
+    pub null_move_pruning: bool,
+    /// Search quiet moves late in the move ordering to a reduced depth.
+    pub late_move_reductions: bool,
+    /// Skip quiet moves near the leaves when the static evaluation is far below alpha.
+    pub futility_pruning: bool,
+    /// Prune nodes near the leaves when the static evaluation is far above beta.
+    pub reverse_futility_pruning: bool,
+    /// Search one ply deeper when in check.
+    pub check_extensions: bool,
+    /// Evaluate with the NNUE network, if one was loaded, instead of the classical evaluation.
+    pub use_nnue: bool,
+    /// How many threads to search with. Every thread searches the whole tree, but they share the
+    /// transposition table, so each one mostly picks up where the others have already been.
+    pub threads: usize,
+    /// How many of the best moves to find the score and principal variation of, instead of just
+    /// the best one. Handy for analysis, but every extra line costs about as much as another
+    /// search of the root.
+    pub multi_pv: usize,
+    /// How well to play, from 0 up to `skill::MAX_SKILL` (full strength). See `skill.rs`.
+    pub skill_level: u8,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            null_move_pruning: true,
+            late_move_reductions: true,
+            futility_pruning: true,
+            reverse_futility_pruning: true,
+            check_extensions: true,
+            use_nnue: true,
+            threads: 1,
+            multi_pv: 1,
+            skill_level: skill::MAX_SKILL,
+        }
+    }
+}
+
+/// Shared between every thread working on a single search. Keeps track of the node count and
+/// decides when the search has to stop.
+struct SearchInfo {
+    /// Set by whoever started the search when they want it stopped. We only ever read it.
+    stop: Arc<AtomicBool>,
+    /// Set once a limit is reached, or the main thread is done, so that every thread stops
+    finished: AtomicBool,
+    nodes: AtomicU64,
+    /// Moves searched with a null window by principal variation search
+    null_window_searches: AtomicU64,
+    /// Null window searches that failed high, and had to be searched again with the full window
+    re_searches: AtomicU64,
+    /// Root searches that fell outside the aspiration window
+    aspiration_fails: AtomicU64,
+    /// Nodes pruned because a null move still failed high
+    null_move_cutoffs: AtomicU64,
+    /// Nodes pruned by reverse futility pruning
+    reverse_futility_prunes: AtomicU64,
+    /// Moves skipped by futility pruning
+    futility_prunes: AtomicU64,
+    /// Captures skipped in the quiescence search because they lose material
+    losing_capture_prunes: AtomicU64,
+    /// Moves searched to a reduced depth
+    reductions: AtomicU64,
+    /// Reduced moves that beat alpha, and had to be searched again to the full depth
+    reduction_re_searches: AtomicU64,
+    start: Instant,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+}
+
+impl SearchInfo {
+    fn new(limits: &SearchLimits, stop: Arc<AtomicBool>) -> SearchInfo {
+        let start = Instant::now();
+        SearchInfo {
+            stop,
+            finished: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+            null_window_searches: AtomicU64::new(0),
+            re_searches: AtomicU64::new(0),
+            aspiration_fails: AtomicU64::new(0),
+            null_move_cutoffs: AtomicU64::new(0),
+            reverse_futility_prunes: AtomicU64::new(0),
+            futility_prunes: AtomicU64::new(0),
+            losing_capture_prunes: AtomicU64::new(0),
+            reductions: AtomicU64::new(0),
+            reduction_re_searches: AtomicU64::new(0),
+            start,
+            deadline: limits.time_budget().map(|budget| start + budget),
+            node_limit: limits.nodes,
+        }
+    }
+
+    /// Counts a node, and returns true if the search should be abandoned
+    fn count_node(&self) -> bool {
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        let out_of_nodes = self.node_limit.is_some_and(|limit| nodes >= limit);
+        let out_of_time = nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+            && self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if out_of_nodes || out_of_time {
+            self.finished.store(true, Ordering::Relaxed);
+        }
+        self.stopped()
+    }
+
+    #[inline(always)]
+    fn stopped(&self) -> bool {
+        self.finished.load(Ordering::Relaxed) || self.stop.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The percentage of null window searches that had to be searched again.
+    fn re_search_rate(&self) -> f64 {
+        let searches = self.null_window_searches.load(Ordering::Relaxed);
+        let re_searches = self.re_searches.load(Ordering::Relaxed);
+        if searches == 0 {
+            0.0
         } else {
-            break
+            100.0 * re_searches as f64 / searches as f64
         }
     }
+}
 
-    println!("PV: {:?}", pv);
-    drop(tt_guard);
+/// What a search found
+#[derive(Clone, Debug)]
+pub struct SearchResult<'a> {
+    /// `None` if the side to move has no legal moves, because it's checkmated or stalemated
+    pub best_move: Option<(&'a Piece, [u8; 2])>,
+    /// From the point of view of the side to move
+    pub score: i32,
+    /// The best `multi_pv` moves (or every move, if there are fewer), best first. The first line
+    /// always starts with `best_move`. Empty if there's no best move.
+    pub lines: Vec<PvLine>,
+}
 
-    println!("{:?}", display);
+/// What the search has found so far, reported for each line once an iteration finishes
+#[derive(Clone, Debug)]
+pub struct SearchReport {
+    pub depth: u8,
+    /// The furthest from the root the main thread has been, including quiescence search
+    pub seldepth: usize,
+    /// Which of the `multi_pv` lines this is, starting at 1
+    pub multipv: usize,
+    /// From the point of view of the side to move
+    pub score: i32,
+    /// Searched by every thread, so far
+    pub nodes: u64,
+    pub nps: u64,
+    /// How full the transposition table is, in permille
+    pub hashfull: usize,
+    /// How many positions were looked up in endgame tablebases. We don't have any, so it's always
+    /// 0, but UCI GUIs expect to be told.
+    pub tbhits: u64,
+    pub time: Duration,
+    pub pv: Vec<TTMove>,
+}
 
-    //if chessboard.turn == MAX_SIDE {
-    //println!("{:?}", tt);
-    scores
-        .iter()
-        .zip(possible_moves)
-        .max_by(|a, b| a.0.cmp(b.0))
-        .unwrap()
-        .1
-    //} else {
-    //scores.iter().zip(possible_moves).min_by(|a, b| a.0.cmp(b.0)).unwrap().1
-    //}
+impl fmt::Display for SearchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Depth {}/{} line {}: {}, {} nodes, {} nps, {} ms, hash {}‰, PV",
+            self.depth,
+            self.seldepth,
+            self.multipv,
+            Score(self.score),
+            self.nodes,
+            self.nps,
+            self.time.as_millis(),
+            self.hashfull
+        )?;
+        for m in self.pv.iter() {
+            write!(f, " {}", m)?;
+        }
+        Ok(())
+    }
 }
 
-// The `moves` are just for debugging
-fn negamax_score(
-    chessboard: &Chessboard,
-    depth: u8,
-    mut alpha: i32,
-    mut beta: i32,
-    moves: Vec<SimpleMove>, // TODO: Get rid of this, we can get the Principle Variation from the TranspositionTable
-    tt: Arc<RwLock<TranspositionTable>>
-) -> i32 {
-    let init_alpha = alpha;
-
-    let tt_guard = tt.read().unwrap();
-    let entry = tt_guard.get(&chessboard);
-    if let Some(entry) = entry {
-        match entry.flag {
-            Flag::Exact => {
-                println!("TT Hit: {:?}", moves);
-                return entry.score
-            },
-            Flag::Alpha => alpha = i32::max(alpha, entry.score),
-            Flag::Beta => beta = i32::min(beta, entry.score),
-        }
-
-        if alpha >= beta {
-            return entry.score;
-        }
-    }
-    // Do this up here to free the RwLock immediately, instead of holdig onto it while we continue
-    // to do work.
-    let mut entry = entry.cloned().unwrap_or_else(|| TTEntry::new(&chessboard));
-    drop(tt_guard);
-
-    if depth == 0 {
-        let score = side_sign(chessboard.turn) * heuristic_score(chessboard);
-        return score;
-    }
-    let mut score = i32::min_value();
-    let mut best_move = None;
-    let possible_moves = chessboard.possible_moves(chessboard.turn);
-
-    if possible_moves.is_empty() {
-        let score = side_sign(chessboard.turn) * heuristic_score(chessboard);
-        return score;
-    }
-
-    for m in possible_moves.iter() {
-        let mut temp = chessboard.clone();
-        temp.try_move(&m.0, m.1, Some(&Piece::Queen));
-        //println!("{:?}, Considering Move: {}", chessboard.turn, SimpleMove(*m));
-        let mut moves = moves.clone();
-        moves.push(SimpleMove(*m));
-
-        let new_score = -negamax_score(&temp, depth - 1, -beta, -alpha, moves, Arc::clone(&tt));
-
-        if new_score > score {
-            use crate::table::{piece_id, TTMove};
-            score = new_score;
-            best_move = Some(TTMove {
-                piece_id: piece_id(m.0),
-                start_pos: m.0.data().position,
-                end_pos: m.1,
+impl SearchResult<'_> {
+    /// The principal variation, starting with `best_move`
+    pub fn pv(&self) -> &[TTMove] {
+        self.lines.first().map_or(&[], |line| &line.moves)
+    }
+}
+
+/// One of the moves the search found, and how it expects the game to go on after it
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    /// From the point of view of the side to move
+    pub score: i32,
+    /// The principal variation, starting with the move itself
+    pub moves: Vec<TTMove>,
+}
+
+impl fmt::Display for PvLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", Score(self.score))?;
+        for m in self.moves.iter() {
+            write!(f, " {}", m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Searches with iterative deepening until one of the `limits` is reached, or `stop` is set.
+/// The result of an iteration that gets interrupted is thrown away, so this returns the best move
+/// of the last iteration that finished. Each iteration that does finish is passed to `report`,
+/// one line at a time.
+pub fn get_best_move<'a>(
+    chessboard: &'a Chessboard,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    report: &mut dyn FnMut(&SearchReport),
+) -> SearchResult<'a> {
+    // there's nothing to search, and the search assumes there's at least one move
+    if chessboard.possible_moves(chessboard.turn).is_empty() {
+        let in_check = chessboard.checkers() != 0;
+        return SearchResult {
+            best_move: None,
+            score: if in_check { -MATE } else { 0 },
+            lines: Vec::new(),
+        };
+    }
+
+    let limits = skill::limit_search(limits, options.skill_level);
+    // A weaker AI searches extra lines to pick from, but only the lines that were asked for are
+    // reported, or returned
+    let requested_lines = options.multi_pv.max(1);
+    let mut options = *options;
+    if options.skill_level < skill::MAX_SKILL {
+        options.multi_pv = options.multi_pv.max(skill::SKILL_MULTI_PV);
+    }
+
+    let info = SearchInfo::new(&limits, stop);
+    tt.new_search();
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+
+    let mut result = thread::scope(|scope| {
+        for id in 1..options.threads {
+            let (info, tt) = (&info, &tt);
+            scope.spawn(move || helper_search(chessboard, info, tt, options, max_depth, id));
+        }
+        let result = main_search(
+            chessboard,
+            &info,
+            &tt,
+            options,
+            max_depth,
+            requested_lines,
+            report,
+        );
+        // the helpers only stop once they're told to
+        info.finished.store(true, Ordering::Relaxed);
+        result
+    });
+
+    let chosen = skill::pick_line(&result.lines, options.skill_level, &mut rand::thread_rng());
+    if chosen > 0 {
+        let first = result.lines[chosen].moves[0];
+        let chosen_move = chessboard
+            .possible_moves(chessboard.turn)
+            .into_iter()
+            .find(|&(piece, end_pos)| TTMove::new(piece, end_pos) == first);
+        if let Some(chosen_move) = chosen_move {
+            log::debug!("Playing line {} instead of the best move", chosen + 1);
+            result.lines[..=chosen].rotate_right(1);
+            result.best_move = Some(chosen_move);
+            result.score = result.lines[0].score;
+        }
+    }
+    result.lines.truncate(requested_lines);
+    result
+}
+
+/// Lazy SMP: the main thread searches just like it would on its own, and is the only one that
+/// reports its progress, or decides when to stop. The helpers search the same position at the
+/// same time, and all they contribute is the entries they leave in the transposition table.
+/// Only the first `reported_lines` of the `multi_pv` lines are passed to `report`.
+fn main_search<'a>(
+    chessboard: &'a Chessboard,
+    info: &SearchInfo,
+    tt: &Arc<TranspositionTable>,
+    options: SearchOptions,
+    max_depth: u8,
+    reported_lines: usize,
+    report: &mut dyn FnMut(&SearchReport),
+) -> SearchResult<'a> {
+    let mut searcher = Searcher::new(info, tt, options, eval_params::params());
+
+    // The best moves are always kept at the front of this list, in order, so they get searched
+    // first in the next iteration. If we run out of time before even the first iteration
+    // finishes, any legal move is better than none.
+    let mut possible_moves = chessboard.possible_moves(chessboard.turn);
+    let multi_pv = options.multi_pv.min(possible_moves.len()).max(1);
+    let mut best_move = possible_moves[0];
+    let mut prev_scores = vec![0; multi_pv];
+    let mut lines = vec![PvLine {
+        score: 0,
+        moves: vec![TTMove::new(best_move.0, best_move.1)],
+    }];
+
+    'deepening: for depth in 1..=max_depth {
+        // Each line is searched without the moves of the lines before it, so the best of the
+        // moves that are left ends up at the front of what's left.
+        let mut scores = Vec::with_capacity(multi_pv);
+        let mut pvs = Vec::with_capacity(multi_pv);
+        for pv_idx in 0..multi_pv {
+            let remaining = &mut possible_moves[pv_idx..];
+            let score =
+                searcher.aspiration_search(chessboard, remaining, depth, prev_scores[pv_idx]);
+            if info.stopped() {
+                log::debug!("Search stopped during depth {}", depth);
+                break 'deepening;
+            }
+            scores.push(score);
+            pvs.push(validated_pv(chessboard, searcher.root_pv()));
+        }
+
+        // the lines weren't searched against each other, so a later one can come out ahead
+        let mut order: Vec<usize> = (0..multi_pv).collect();
+        order.sort_by_key(|&i| Reverse(scores[i]));
+        let best_moves: Vec<_> = order.iter().map(|&i| possible_moves[i]).collect();
+        possible_moves[..multi_pv].copy_from_slice(&best_moves);
+        prev_scores = order.iter().map(|&i| scores[i]).collect();
+        lines = order
+            .iter()
+            .map(|&i| PvLine {
+                score: scores[i],
+                moves: std::mem::take(&mut pvs[i]),
+            })
+            .collect();
+
+        best_move = possible_moves[0];
+
+        let elapsed = info.start.elapsed();
+        let nodes = info.nodes.load(Ordering::Relaxed);
+        let hashfull = tt.hashfull();
+        for (i, line) in lines.iter().take(reported_lines).enumerate() {
+            report(&SearchReport {
+                depth,
+                seldepth: searcher.seldepth,
+                multipv: i + 1,
+                score: line.score,
+                nodes,
+                nps: nodes * 1000 / (elapsed.as_millis() as u64).max(1),
+                hashfull,
+                tbhits: 0,
+                time: elapsed,
+                pv: line.moves.clone(),
             });
         }
+        log::debug!(
+            "Re-searches {:.1}%, aspiration fails {}",
+            info.re_search_rate(),
+            info.aspiration_fails.load(Ordering::Relaxed),
+        );
+        log::debug!(
+            "Pruning: {} null move cutoffs, {} reverse futility, {} futile moves, \
+             {} losing captures, {}/{} reductions re-searched",
+            info.null_move_cutoffs.load(Ordering::Relaxed),
+            info.reverse_futility_prunes.load(Ordering::Relaxed),
+            info.futility_prunes.load(Ordering::Relaxed),
+            info.losing_capture_prunes.load(Ordering::Relaxed),
+            info.reduction_re_searches.load(Ordering::Relaxed),
+            info.reductions.load(Ordering::Relaxed),
+        );
+
+        // Each iteration takes several times longer than the last, so if we've already used half
+        // our time, we won't be able to finish the next one.
+        if let Some(deadline) = info.deadline {
+            if elapsed * 2 >= deadline - info.start {
+                break;
+            }
+        }
+    }
+
+    SearchResult {
+        best_move: Some(best_move),
+        score: lines[0].score,
+        lines,
+    }
+}
+
+/// Searches with iterative deepening until it's stopped. Every other helper starts one ply deeper
+/// than the main thread, so they're often done with the next iteration's positions by the time
+/// the main thread gets to them.
+fn helper_search(
+    chessboard: &Chessboard,
+    info: &SearchInfo,
+    tt: &Arc<TranspositionTable>,
+    options: SearchOptions,
+    max_depth: u8,
+    id: usize,
+) {
+    let mut searcher = Searcher::new(info, tt, options, eval_params::params());
+    let mut possible_moves = chessboard.possible_moves(chessboard.turn);
+    let mut prev_score = 0;
 
-        alpha = i32::max(alpha, score);
-        if alpha >= beta {
+    let first_depth = (1 + id % 2) as u8;
+    for depth in first_depth..=max_depth {
+        prev_score = searcher.aspiration_search(chessboard, &mut possible_moves, depth, prev_score);
+        if info.stopped() {
             break;
         }
     }
+}
 
-    entry.score = score;
-    entry.best_move = best_move;
-    if score <= init_alpha {
-        entry.flag = Flag::Alpha;
-    } else if score >= beta {
-        entry.flag = Flag::Beta;
-    } else {
-        entry.flag = Flag::Exact;
+/// The longest start of `pv` that can actually be played from `chessboard`. The search only puts
+/// legal moves in its PV, so this should be all of it, but it's cheap enough to make sure.
+fn validated_pv(chessboard: &Chessboard, pv: &[TTMove]) -> Vec<TTMove> {
+    let mut board = chessboard.clone();
+    let mut valid = Vec::with_capacity(pv.len());
+
+    for &m in pv {
+        let piece = match board.piece_at(m.start_pos) {
+            Some(piece) if TTMove::new(piece, m.end_pos) == m => piece.clone(),
+            _ => break,
+        };
+        let legal = board
+            .possible_moves(board.turn)
+            .iter()
+            .any(|&(p, end_pos)| p.data().position == m.start_pos && end_pos == m.end_pos);
+        if !legal {
+            log::warn!("Illegal move {} in the PV after {:?}", m, valid);
+            break;
+        }
+        board.try_move(&piece, m.end_pos, Some(&Piece::Queen));
+        valid.push(m);
     }
-    entry.depth = depth;
-    tt.write().unwrap().store(entry);
+    valid
+}
+
+/// Plays out the principal variation of the quiescence search from each of the `chessboards`, and
+/// returns the quiet positions at the end of them, where the static evaluation can be trusted, in
+/// the same order. Used for tuning the evaluation.
+pub fn quiet_positions<'b>(
+    chessboards: impl IntoIterator<Item = &'b Chessboard>,
+    params: &EvalParams,
+) -> Vec<Chessboard> {
+    let info = SearchInfo::new(&SearchLimits::default(), Arc::new(AtomicBool::new(false)));
+    // the quiescence search never looks at the transposition table, so it may as well be tiny
+    let tt = Arc::new(TranspositionTable::new(0));
+    // the parameters are what's being tuned, so the network mustn't stand in for them
+    let options = SearchOptions {
+        use_nnue: false,
+        ..SearchOptions::default()
+    };
+    // setting up a searcher (and its evaluator's caches) isn't free, so they all share one
+    let mut searcher = Searcher::new(&info, &tt, options, params);
+    chessboards
+        .into_iter()
+        .map(|chessboard| searcher.quiet_position(chessboard))
+        .collect()
+}
 
-    score
+/// Everything a single search thread needs. The `SearchInfo` and `TranspositionTable` are shared
+/// with the other threads, but the move ordering tables and evaluator (with its caches) belong to
+/// this thread alone.
+struct Searcher<'a> {
+    info: &'a SearchInfo,
+    tt: &'a Arc<TranspositionTable>,
+    tables: SearchTables,
+    evaluator: Box<dyn Evaluator + 'a>,
+    options: SearchOptions,
+    /// Cleared while searching a null move, so the child doesn't try another one straight away.
+    allow_null_move: bool,
+    /// The triangular PV table: `pv_table[ply]` is the best line found from the node being searched
+    /// at `ply`. Each node clears its line when it starts, and when a move raises alpha, the line
+    /// becomes that move followed by the child's line from `pv_table[ply + 1]`.
+    pv_table: Vec<Vec<TTMove>>,
+    /// The furthest from the root this thread has searched
+    seldepth: usize,
 }
 
-fn heuristic_score(chessboard: &Chessboard) -> i32 {
-    // assume stalemate = 0
-    // only the side to move (whose turn it is) can be in checkmate
-    if chessboard.is_checkmated(chessboard.turn) == Checkmate::Checkmate {
-        // if this move checkmates, just return an incredibly high number
-        // for the other side (the winning side)
-        return 1000 * side_sign(chessboard.turn.other());
+impl<'a> Searcher<'a> {
+    fn new(
+        info: &'a SearchInfo,
+        tt: &'a Arc<TranspositionTable>,
+        options: SearchOptions,
+        params: &'a EvalParams,
+    ) -> Searcher<'a> {
+        Searcher {
+            info,
+            tt,
+            tables: SearchTables::new(),
+            evaluator: eval::evaluator(params, options.use_nnue),
+            options,
+            allow_null_move: true,
+            // quiescence search can carry on a few plies past MAX_PLY
+            pv_table: vec![Vec::new(); MAX_PLY + MAX_QUIESCENCE_DEPTH as usize + 2],
+            seldepth: 0,
+        }
+    }
+
+    /// The principal variation of the last search of the root
+    fn root_pv(&self) -> &[TTMove] {
+        &self.pv_table[0]
     }
 
-    let mut score = 0;
-    // only the side to move (chessboard.turn) can be in check
-    // because otherwise, they'd have gotton themselves out of check
-    if chessboard.is_side_in_check(chessboard.turn) {
-        score += 2 * side_sign(chessboard.turn.other());
+    /// Makes the line at `ply` into `m` followed by the child's line
+    fn update_pv(&mut self, ply: usize, m: TTMove) {
+        let (lines, child_lines) = self.pv_table.split_at_mut(ply + 1);
+        let line = &mut lines[ply];
+        line.clear();
+        line.push(m);
+        line.extend_from_slice(&child_lines[0]);
     }
 
-    for piece in chessboard.pieces().values() {
-        score += piece_value(piece) * side_sign(piece.data().side);
+    /// The position at the end of the quiescence search's principal variation from `chessboard`
+    fn quiet_position(&mut self, chessboard: &Chessboard) -> Chessboard {
+        let mut board = chessboard.clone();
+
+        for ply in 0..MAX_QUIESCENCE_DEPTH {
+            let in_check = board.checkers() != 0;
+            let possible_moves = if in_check {
+                board.possible_moves(board.turn)
+            } else {
+                board.possible_captures(board.turn)
+            };
+
+            // the same choice `quiescence` makes: standing pat, unless a capture does better
+            let mut best_score = if in_check { -INFINITY } else { self.static_eval(&board) };
+            let mut best_child = None;
+            for m in possible_moves.iter() {
+                let mut temp = board.clone();
+                temp.try_move(m.0, m.1, Some(&Piece::Queen));
+                let depth = MAX_QUIESCENCE_DEPTH - ply - 1;
+                let score =
+                    -self.quiescence(&temp, ply as usize + 1, -INFINITY, -best_score, depth);
+                if score > best_score {
+                    best_score = score;
+                    best_child = Some(temp);
+                }
+            }
+
+            match best_child {
+                Some(child) => board = child,
+                None => break,
+            }
+        }
+
+        board
+    }
+
+    /// The static evaluation, from the point of view of the side to move
+    fn static_eval(&mut self, chessboard: &Chessboard) -> i32 {
+        side_sign(chessboard.turn) * self.evaluator.evaluate(chessboard)
+    }
+
+    /// Searches the root with a narrow window around `prev_score`, the score of the previous
+    /// iteration, widening the window and searching again whenever the result falls outside of
+    /// it.
+    fn aspiration_search(
+        &mut self,
+        chessboard: &Chessboard,
+        possible_moves: &mut [(&Piece, [u8; 2])],
+        depth: u8,
+        prev_score: i32,
+    ) -> i32 {
+        let mut delta = ASPIRATION_WINDOW;
+        // a window around a mate score would take forever to widen to where the score really is
+        let (mut alpha, mut beta) = if depth >= ASPIRATION_MIN_DEPTH && prev_score.abs() < MATE_BOUND {
+            (
+                prev_score.saturating_sub(delta).max(-INFINITY),
+                prev_score.saturating_add(delta).min(INFINITY),
+            )
+        } else {
+            (-INFINITY, INFINITY)
+        };
+
+        loop {
+            let score = self.search_root(chessboard, possible_moves, depth, alpha, beta);
+            if self.info.stopped() {
+                return score;
+            }
+
+            if score <= alpha && alpha > -INFINITY {
+                alpha = score.saturating_sub(delta).max(-INFINITY);
+            } else if score >= beta && beta < INFINITY {
+                beta = score.saturating_add(delta).min(INFINITY);
+            } else {
+                return score;
+            }
+            SearchInfo::count(&self.info.aspiration_fails);
+            delta = delta.saturating_mul(2);
+        }
     }
 
-    score
+    /// Searches every root move with principal variation search, and moves the best one to the
+    /// front of `possible_moves`.
+    fn search_root(
+        &mut self,
+        chessboard: &Chessboard,
+        possible_moves: &mut [(&Piece, [u8; 2])],
+        depth: u8,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        let mut best_score = -INFINITY;
+        let mut best_idx = 0;
+        self.pv_table[0].clear();
+
+        for (i, m) in possible_moves.iter().enumerate() {
+            let mut temp = chessboard.clone();
+            temp.try_move(m.0, m.1, Some(&Piece::Queen));
+            //println!("Considering Move: {}", SimpleMove(*m));
+
+            let score = self.pvs_child(&temp, depth - 1, 0, 0, i == 0, alpha, beta);
+            if self.info.stopped() {
+                return 0;
+            }
+
+            // even if every move fails low, the best one's line is better than nothing
+            if score > best_score {
+                best_score = score;
+                best_idx = i;
+                self.update_pv(0, TTMove::new(m.0, m.1));
+            }
+            alpha = i32::max(alpha, score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        possible_moves[..=best_idx].rotate_right(1);
+        best_score
+    }
+
+    /// Searches a child of the current node (already made on `temp`) to `new_depth`, returning
+    /// its score from our perspective. The first move is assumed to be the best, and gets the full
+    /// window. The rest are only searched with a null window (and `reduction` plies shallower) to
+    /// prove that they're worse, and are searched again properly if that turns out not to be the
+    /// case.
+    #[allow(clippy::too_many_arguments)]
+    fn pvs_child(
+        &mut self,
+        temp: &Chessboard,
+        new_depth: u8,
+        reduction: u8,
+        ply: usize,
+        first: bool,
+        alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if first {
+            return -self.negamax_score(temp, new_depth, ply + 1, -beta, -alpha);
+        }
+
+        SearchInfo::count(&self.info.null_window_searches);
+        let reduced_depth = new_depth.saturating_sub(reduction);
+        let mut score = -self.negamax_score(temp, reduced_depth, ply + 1, -alpha - 1, -alpha);
+
+        if reduction > 0 {
+            SearchInfo::count(&self.info.reductions);
+            if score > alpha && !self.info.stopped() {
+                SearchInfo::count(&self.info.reduction_re_searches);
+                score = -self.negamax_score(temp, new_depth, ply + 1, -alpha - 1, -alpha);
+            }
+        }
+
+        if score > alpha && score < beta && !self.info.stopped() {
+            SearchInfo::count(&self.info.re_searches);
+            score = -self.negamax_score(temp, new_depth, ply + 1, -beta, -alpha);
+        }
+        score
+    }
+
+    fn negamax_score(
+        &mut self,
+        chessboard: &Chessboard,
+        mut depth: u8,
+        ply: usize,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> i32 {
+        // before anything else, so a line from an earlier sibling can't end up in our parent's PV
+        self.pv_table[ply].clear();
+        self.seldepth = self.seldepth.max(ply);
+        let allow_null_move = std::mem::replace(&mut self.allow_null_move, true);
+
+        let in_check = chessboard.checkers() != 0;
+        if in_check && self.options.check_extensions && ply < MAX_PLY {
+            depth += 1;
+        }
+
+        if depth == 0 || ply >= MAX_PLY {
+            return self.quiescence(chessboard, ply, alpha, beta, MAX_QUIESCENCE_DEPTH);
+        }
+
+        if self.info.count_node() {
+            // the result will be thrown away, so it doesn't matter what we return
+            return 0;
+        }
+
+        let init_alpha = alpha;
+        // None of the pruning is safe in PV nodes (where we need an exact score) or in check.
+        let pv_node = beta.saturating_sub(alpha) > 1;
+
+        let entry = self.tt.get(chessboard);
+        if let Some(entry) = &entry {
+            // A shallower search isn't good enough to use, but the entry is still worth updating.
+            // PV nodes carry on anyway, so the principal variation doesn't get cut short.
+            if entry.depth >= depth && !pv_node {
+                let tt_score = score_from_tt(entry.score, ply);
+                match entry.flag {
+                    Flag::Exact => {
+                        log::trace!("TT Hit: {} at depth {}", Score(tt_score), entry.depth);
+                        return tt_score
+                    },
+                    Flag::Alpha => beta = i32::min(beta, tt_score),
+                    Flag::Beta => alpha = i32::max(alpha, tt_score),
+                }
+
+                if alpha >= beta {
+                    return tt_score;
+                }
+            }
+        }
+        let mut entry = entry.unwrap_or_else(|| TTEntry::new(chessboard));
+        let static_eval = if pv_node || in_check {
+            None
+        } else {
+            Some(self.static_eval(chessboard))
+        };
+
+        if let Some(eval) = static_eval {
+            if self.options.reverse_futility_pruning
+                && depth <= REVERSE_FUTILITY_MAX_DEPTH
+                && eval - REVERSE_FUTILITY_MARGIN * i32::from(depth) >= beta
+            {
+                SearchInfo::count(&self.info.reverse_futility_prunes);
+                return eval;
+            }
+
+            // Pawn-only endgames are where zugzwang happens, and where passing would be the best
+            // move if it were allowed, so null moves can't be trusted there.
+            if self.options.null_move_pruning
+                && allow_null_move
+                && depth >= NULL_MOVE_MIN_DEPTH
+                && eval >= beta
+                && chessboard.has_non_pawn_material(chessboard.turn)
+            {
+                let reduction = if depth > 6 { 3 } else { 2 };
+                let mut temp = chessboard.clone();
+                temp.make_null_move();
+
+                self.allow_null_move = false;
+                let null_depth = depth.saturating_sub(1 + reduction);
+                let score = -self.negamax_score(&temp, null_depth, ply + 1, -beta, -beta + 1);
+                self.allow_null_move = true;
+
+                if self.info.stopped() {
+                    return 0;
+                }
+                if score >= beta {
+                    SearchInfo::count(&self.info.null_move_cutoffs);
+                    return beta;
+                }
+            }
+        }
+
+        let futile = self.options.futility_pruning
+            && (depth as usize) < FUTILITY_MARGINS.len()
+            && static_eval.is_some_and(|eval| eval + FUTILITY_MARGINS[depth as usize] <= alpha);
+
+        let mut score = i32::MIN;
+        let mut best_move = None;
+        let mut possible_moves = chessboard.possible_moves(chessboard.turn);
+
+        if possible_moves.is_empty() {
+            return if in_check { -(MATE - ply as i32) } else { 0 };
+        }
+
+        self.tables
+            .order_moves(chessboard, &mut possible_moves, entry.best_move, ply);
+
+        for (i, m) in possible_moves.iter().enumerate() {
+            let quiet = mvv_lva(chessboard, *m).is_none();
+            let mut temp = chessboard.clone();
+            temp.try_move(m.0, m.1, Some(&Piece::Queen));
+            //println!("{:?}, Considering Move: {}", chessboard.turn, SimpleMove(*m));
+            let gives_check = temp.checkers() != 0;
+
+            // the first move is always searched, so we have some score to return
+            if futile && quiet && !gives_check && i > 0 {
+                SearchInfo::count(&self.info.futility_prunes);
+                continue;
+            }
+
+            let reduction = if self.options.late_move_reductions
+                && depth >= LMR_MIN_DEPTH
+                && i >= LMR_MIN_MOVES
+                && quiet
+                && !in_check
+                && !gives_check
+            {
+                if depth >= 6 && i >= 2 * LMR_MIN_MOVES {
+                    2
+                } else {
+                    1
+                }
+            } else {
+                0
+            };
+
+            let new_score = self.pvs_child(&temp, depth - 1, reduction, ply, i == 0, alpha, beta);
+            if self.info.stopped() {
+                return 0;
+            }
+
+            if new_score > score {
+                score = new_score;
+                best_move = Some(TTMove::new(m.0, m.1));
+                if score > alpha {
+                    self.update_pv(ply, TTMove::new(m.0, m.1));
+                }
+            }
+
+            alpha = i32::max(alpha, score);
+            if alpha >= beta {
+                if quiet {
+                    self.tables.record_cutoff(TTMove::new(m.0, m.1), depth, ply);
+                }
+                break;
+            }
+        }
+
+        entry.score = score_to_tt(score, ply);
+        entry.best_move = best_move;
+        if score <= init_alpha {
+            entry.flag = Flag::Alpha;
+        } else if score >= beta {
+            entry.flag = Flag::Beta;
+        } else {
+            entry.flag = Flag::Exact;
+        }
+        entry.depth = depth;
+        self.tt.store(entry);
+
+        score
+    }
+
+    /// Keeps searching captures and promotions past the horizon until the position is quiet, so
+    /// that we never stop in the middle of an exchange (the horizon effect). The side to move may
+    /// always "stand pat" and decline to capture, unless it is in check, in which case every
+    /// evasion is searched instead.
+    fn quiescence(
+        &mut self,
+        chessboard: &Chessboard,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        depth: u8,
+    ) -> i32 {
+        self.pv_table[ply].clear();
+        self.seldepth = self.seldepth.max(ply);
+        if self.info.count_node() {
+            return 0;
+        }
+
+        let in_check = chessboard.checkers() != 0;
+        let stand_pat = self.static_eval(chessboard);
+
+        if depth == 0 {
+            return stand_pat;
+        }
+
+        let (mut score, mut possible_moves) = if in_check {
+            // there is no standing pat while in check
+            let evasions = chessboard.possible_moves(chessboard.turn);
+            if evasions.is_empty() {
+                return -(MATE - ply as i32);
+            }
+            (i32::MIN, evasions)
+        } else {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = i32::max(alpha, stand_pat);
+            (stand_pat, chessboard.possible_captures(chessboard.turn))
+        };
+
+        possible_moves.sort_by_cached_key(|m| -mvv_lva(chessboard, *m).unwrap_or(0));
+
+        for m in possible_moves.iter() {
+            if !in_check && stand_pat + material_gain(chessboard, *m) + DELTA_MARGIN < alpha {
+                // delta pruning: this capture can't possibly get us back to alpha
+                continue;
+            }
+            if !in_check && chessboard.see(*m) < 0 {
+                // the exchange loses material, so standing pat is better
+                SearchInfo::count(&self.info.losing_capture_prunes);
+                continue;
+            }
+
+            let mut temp = chessboard.clone();
+            temp.try_move(m.0, m.1, Some(&Piece::Queen));
+
+            let new_score = -self.quiescence(&temp, ply + 1, -beta, -alpha, depth - 1);
+            if self.info.stopped() {
+                return 0;
+            }
+
+            if new_score > alpha {
+                self.update_pv(ply, TTMove::new(m.0, m.1));
+            }
+            score = i32::max(score, new_score);
+            alpha = i32::max(alpha, score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        score
+    }
+}
+
+/// The most material the side to move can win with `m`: the value of the captured piece, plus the
+/// value gained by promoting.
+fn material_gain(chessboard: &Chessboard, m: (&Piece, [u8; 2])) -> i32 {
+    let (piece, end_pos) = m;
+    let mut gain = match chessboard.piece_at(end_pos) {
+        Some(captured) => piece_value(captured),
+        // a pawn moving diagonally to an empty square is en passant
+        None if matches!(piece, Piece::Pawn(_)) && piece.data().position[0] != end_pos[0] => {
+            piece_value(piece)
+        }
+        None => 0,
+    };
+    if matches!(piece, Piece::Pawn(_)) && end_pos[1] == piece.data().side.other().back_rank() {
+        gain += piece_value(&Piece::Queen(piece.data().clone())) - piece_value(piece);
+    }
+    gain
 }
 
-fn piece_value(piece: &Piece) -> i32 {
+/// A rough value (in centipawns) for each piece, used for ordering captures and pruning. The
+/// evaluation has its own, more precise values.
+pub fn piece_value(piece: &Piece) -> i32 {
     match piece {
-        Piece::Pawn(_) => 1,
-        Piece::Knight(_) => 3,
-        Piece::Bishop(_) => 3,
-        Piece::Rook(_) => 5,
-        Piece::Queen(_) => 9,
+        Piece::Pawn(_) => 100,
+        Piece::Knight(_) => 300,
+        Piece::Bishop(_) => 300,
+        Piece::Rook(_) => 500,
+        Piece::Queen(_) => 900,
         Piece::King(_) => 0, // both sides should have a king
     }
 }
 
+/// How many moves until the side to move mates (positive), or gets mated (negative), if `score`
+/// is a mate score.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score + 1) / 2)
+    } else {
+        None
+    }
+}
+
+/// Displays a score as "mate in N", "mated in N", or just the number.
+pub struct Score(pub i32);
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match mate_in(self.0) {
+            Some(moves) if moves > 0 => write!(f, "mate in {}", moves),
+            Some(moves) => write!(f, "mated in {}", -moves),
+            None => write!(f, "score {}", self.0),
+        }
+    }
+}
+
 // This is literally only used for debugging the ai
 #[derive(Clone)]
 pub struct SimpleMove<'a>(pub (&'a Piece, [u8; 2]));
@@ -247,3 +1165,83 @@ impl fmt::Debug for SimpleMove<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::make_uci_move;
+
+    /// Searches to `depth` with the default options, without reporting anything
+    fn search<'a>(
+        chessboard: &'a Chessboard,
+        depth: u8,
+        tt: &Arc<TranspositionTable>,
+    ) -> SearchResult<'a> {
+        let limits = SearchLimits { depth: Some(depth), ..SearchLimits::default() };
+        let options = SearchOptions::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        get_best_move(chessboard, &limits, &options, tt.clone(), stop, &mut |_| {})
+    }
+
+    #[test]
+    fn finds_mate_in_two() {
+        // Qb8+ Nxb8 Rd8#
+        let fen = "4kb1r/p2n1ppp/4q3/4p1B1/4P3/1Q6/PPP2PPP/2KR4 w k - 1 17";
+        let chessboard = Chessboard::from_fen(String::from(fen));
+        let tt = Arc::new(TranspositionTable::new(1));
+
+        // once with an empty table, and then again with the first search's entries in it
+        for _ in 0..2 {
+            let result = search(&chessboard, 5, &tt);
+            assert_eq!(mate_in(result.score), Some(2));
+            let (piece, end_pos) = result.best_move.unwrap();
+            assert_eq!(TTMove::new(piece, end_pos).to_string(), "b3b8");
+        }
+
+        // the table's mate scores are for the positions they were stored for, not the old root
+        let mut chessboard = chessboard.clone();
+        assert!(make_uci_move(&mut chessboard, "b3b8") && make_uci_move(&mut chessboard, "d7b8"));
+        let result = search(&chessboard, 5, &tt);
+        assert_eq!(mate_in(result.score), Some(1));
+    }
+
+    #[test]
+    fn principal_variations_can_be_played() {
+        let positions = [
+            crate::chessboard::STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "8/8/4k3/8/2K5/8/3Q4/8 w - - 0 1",
+        ];
+        for fen in positions.iter() {
+            let chessboard = Chessboard::from_fen(String::from(*fen));
+            let result = search(&chessboard, 5, &Arc::new(TranspositionTable::new(1)));
+            let (piece, end_pos) = result.best_move.unwrap();
+            let pv = &result.lines[0].moves;
+            assert_eq!(pv.first(), Some(&TTMove::new(piece, end_pos)), "{}", fen);
+
+            let mut board = chessboard.clone();
+            let mut seen = vec![board.hash()];
+            for m in pv {
+                let m = m.to_string();
+                assert!(make_uci_move(&mut board, &m), "{} in {:?} from {}", m, pv, fen);
+                assert!(!seen.contains(&board.hash()), "{:?} repeats from {}", pv, fen);
+                seen.push(board.hash());
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_the_stop_flag_alone() {
+        // running out of nodes, and stopping the helper threads, is the search's own business
+        let chessboard = Chessboard::from_fen(String::from(crate::chessboard::STARTPOS));
+        let limits = SearchLimits { nodes: Some(2000), ..SearchLimits::default() };
+        let options = SearchOptions { threads: 2, ..SearchOptions::default() };
+        let tt = Arc::new(TranspositionTable::new(1));
+        let stop = Arc::new(AtomicBool::new(false));
+        let result = get_best_move(&chessboard, &limits, &options, tt, stop.clone(), &mut |_| {});
+        assert!(result.best_move.is_some());
+        assert!(!stop.load(Ordering::Relaxed));
+    }
+}
+
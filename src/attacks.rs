@@ -0,0 +1,196 @@
+use crate::bitboard::{
+    between, bishop_attacks, bits, pawn_attacks, piece_attacks, rank_mask, rook_attacks,
+};
+use crate::chessboard::Chessboard;
+use crate::piece::{kind_idx, side_idx, Piece, Side, BISHOP, KING, QUEEN, ROOK};
+
+// Everything here is worked out from the bitboards the chessboard keeps up to date, and returns
+// a bitboard (bit `rank * 8 + file` for each square).
+
+impl Chessboard {
+    /// Every piece of `side` that attacks `pos`, whether or not there's a piece on it
+    pub fn attackers_to(&self, pos: [u8; 2], side: Side) -> u64 {
+        let bitboards = self.bitboards();
+        bitboards.attackers_to(square_idx(pos), side, bitboards.occupied)
+    }
+
+    /// Every square `side` attacks, including the ones its own pieces are on (which it defends)
+    pub fn attacked_squares(&self, side: Side) -> u64 {
+        self.bitboards().attacked_by(side)
+    }
+
+    /// Every piece of `side` that can't leave the line between an enemy slider and its own king,
+    /// without exposing the king
+    pub fn pinned_pieces(&self, side: Side) -> u64 {
+        let bitboards = self.bitboards();
+        let king = match self.king_square(side) {
+            Some(king) => king,
+            None => return 0,
+        };
+        let theirs = &bitboards.pieces[side_idx(side.other())];
+        let their_occupied = bitboards.occupied_by[side_idx(side.other())];
+
+        // enemy sliders that would attack the king if none of our pieces were in the way
+        let snipers = bishop_attacks(king, their_occupied) & (theirs[BISHOP] | theirs[QUEEN])
+            | rook_attacks(king, their_occupied) & (theirs[ROOK] | theirs[QUEEN]);
+
+        let mut pinned = 0;
+        for sniper in bits(snipers) {
+            let blockers = between(king, sniper) & bitboards.occupied;
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & bitboards.occupied_by[side_idx(side)];
+            }
+        }
+        pinned
+    }
+
+    /// The pieces giving check to the side to move
+    pub fn checkers(&self) -> u64 {
+        match self.king_square(self.turn) {
+            Some(king) => {
+                let bitboards = self.bitboards();
+                bitboards.attackers_to(king, self.turn.other(), bitboards.occupied)
+            }
+            None => 0,
+        }
+    }
+
+    /// Where `side`'s king is, if it's on the board
+    pub fn king_square(&self, side: Side) -> Option<usize> {
+        bits(self.bitboards().pieces[side_idx(side)][KING]).next()
+    }
+
+    /// Every square `piece` might be able to move to: the squares it attacks that its own side
+    /// isn't on, plus the squares pawns push to and kings castle to. Whether each of these moves
+    /// is legal still has to be checked, but it's far quicker than checking every square.
+    pub fn candidate_targets(&self, piece: &Piece) -> u64 {
+        let data = piece.data();
+        let bitboards = self.bitboards();
+        let sq = square_idx(data.position);
+        let bit = 1 << sq;
+        let targets = match piece {
+            // one or two squares forward (bits pushed off the board just disappear), or a capture
+            Piece::Pawn(_) => {
+                let forward = match data.side {
+                    Side::Light => bit << 8 | bit << 16,
+                    Side::Dark => bit >> 8 | bit >> 16,
+                };
+                forward | pawn_attacks(bit, data.side)
+            }
+            Piece::King(_) => {
+                let castling = (bit << 2 | bit >> 2) & rank_mask(sq / 8);
+                piece_attacks(KING, sq, bitboards.occupied) | castling
+            }
+            _ => piece_attacks(kind_idx(piece), sq, bitboards.occupied),
+        };
+        targets & !bitboards.occupied_by[side_idx(data.side)]
+    }
+
+    /// Whether `side` attacks `pos`
+    #[inline(always)]
+    pub fn is_attacked(&self, pos: [u8; 2], side: Side) -> bool {
+        self.attackers_to(pos, side) != 0
+    }
+}
+
+/// The square's bit in a bitboard: a1 is 0, b1 is 1, and h8 is 63. Everything that numbers the
+/// squares uses this.
+#[inline(always)]
+pub fn square_idx(pos: [u8; 2]) -> usize {
+    (pos[1] * 8 + pos[0]) as usize
+}
+
+/// The inverse of `square_idx`
+#[inline(always)]
+pub fn square_pos(sq: usize) -> [u8; 2] {
+    [(sq % 8) as u8, (sq / 8) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::square_bit;
+    use crate::chessboard::STARTPOS;
+    use crate::piece::MoveType;
+
+    fn board(fen: &str) -> Chessboard {
+        Chessboard::from_fen(fen.to_string())
+    }
+
+    /// Where the piece on `from` can legally move, checking every square on the board
+    fn legal_targets(chessboard: &Chessboard, from: [u8; 2]) -> u64 {
+        let piece = chessboard.piece_at(from).unwrap();
+        (0..64)
+            .filter(|&sq| {
+                piece.can_move(chessboard, square_pos(sq), true, Some(&Piece::Queen))
+                    != MoveType::Invalid
+            })
+            .fold(0, |targets, sq| targets | 1 << sq)
+    }
+
+    #[test]
+    fn pins_along_a_file() {
+        // the rook on e2 can only move up and down the e-file
+        let chessboard = board("4r2k/8/8/8/8/8/4R3/4K3 w - - 0 1");
+        assert_eq!(chessboard.pinned_pieces(Side::Light), square_bit([4, 1]));
+        let expected = (2..8).fold(0, |targets, rank| targets | square_bit([4, rank]));
+        assert_eq!(legal_targets(&chessboard, [4, 1]), expected);
+    }
+
+    #[test]
+    fn pins_along_a_diagonal() {
+        // the knight on c2 can't move at all, and the bishop on e2 is behind the king
+        let chessboard = board("7k/8/8/8/b7/8/2N1B3/3K4 w - - 0 1");
+        assert_eq!(chessboard.pinned_pieces(Side::Light), square_bit([2, 1]));
+        assert_eq!(legal_targets(&chessboard, [2, 1]), 0);
+        assert_ne!(legal_targets(&chessboard, [4, 1]), 0);
+    }
+
+    #[test]
+    fn only_the_king_moves_out_of_double_check() {
+        // the rook on e8 and the knight on f3 both give check, so taking the knight with the
+        // queen doesn't help
+        let chessboard = board("4r2k/8/8/8/8/5n1Q/8/4K3 w - - 0 1");
+        assert_eq!(chessboard.checkers().count_ones(), 2);
+        let moves = chessboard.possible_moves(Side::Light);
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|(piece, _)| matches!(piece, Piece::King(_))));
+    }
+
+    #[test]
+    fn en_passant_cant_expose_the_king_along_the_rank() {
+        // exd6 takes both pawns off the fifth rank, so the rook on h5 would attack the king on a5
+        let pinned = board("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1");
+        assert_eq!(pinned.pinned_pieces(Side::Light), 0);
+        assert_eq!(legal_targets(&pinned, [4, 4]), square_bit([4, 5]));
+
+        let free = board("8/8/8/K2pP3/8/8/8/7k w - d6 0 1");
+        assert_eq!(
+            free.piece_at([4, 4])
+                .unwrap()
+                .can_move(&free, [3, 5], true, Some(&Piece::Queen)),
+            MoveType::EnPassant
+        );
+    }
+
+    #[test]
+    fn candidate_targets_include_every_legal_move() {
+        let positions = [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+        for fen in positions.iter() {
+            let chessboard = board(fen);
+            for piece in chessboard.pieces().values() {
+                let position = piece.data().position;
+                let legal = legal_targets(&chessboard, position);
+                let candidates = chessboard.candidate_targets(piece);
+                assert_eq!(legal & !candidates, 0, "{} from {:?}", fen, position);
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use chess::cli::{self, fail};
+use chess::tune;
+
+// `tune [options] <positions> [output]` tunes the evaluation parameters (see `tune::run`), and
+// writes them to `output` (`tuned_params.txt` by default). It takes the same logging, `--params`
+// and `--net` options as `chess`, but doesn't need the GUI, so it can be built on machines that
+// can't open a window, with `cargo build --release --no-default-features --bin tune`.
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    cli::take_common_options(&mut args);
+
+    let positions = args
+        .get(1)
+        .unwrap_or_else(|| fail("tune needs a file of positions"));
+    let output = args.get(2).map_or("tuned_params.txt", String::as_str);
+    if let Err(e) = tune::run(positions, output) {
+        fail(format!("Couldn't tune the evaluation parameters: {}", e));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::attacks::square_idx;
+use crate::piece::{kind_idx, side_idx, Piece, Side, BISHOP, KING, KNIGHT, PAWN, QUEEN, ROOK};
+use std::collections::HashMap;
+
+// Bitboards have one bit for each square, numbered `rank * 8 + file`, so a1 is bit 0, h1 is bit 7
+// and h8 is bit 63.
+
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+pub const FILE_H: u64 = FILE_A << 7;
+
+const KNIGHT_OFFSETS: [[i8; 2]; 8] = [
+    [1, 2],
+    [2, 1],
+    [2, -1],
+    [1, -2],
+    [-1, -2],
+    [-2, -1],
+    [-2, 1],
+    [-1, 2],
+];
+const KING_OFFSETS: [[i8; 2]; 8] = [
+    [0, 1],
+    [1, 1],
+    [1, 0],
+    [1, -1],
+    [0, -1],
+    [-1, -1],
+    [-1, 0],
+    [-1, 1],
+];
+const BISHOP_DIRECTIONS: [[i8; 2]; 4] = [[1, 1], [1, -1], [-1, -1], [-1, 1]];
+const ROOK_DIRECTIONS: [[i8; 2]; 4] = [[0, 1], [1, 0], [0, -1], [-1, 0]];
+
+/// The squares a knight on each square attacks
+pub static KNIGHT_ATTACKS: [u64; 64] = leaper_attacks(&KNIGHT_OFFSETS);
+/// The squares a king on each square attacks
+pub static KING_ATTACKS: [u64; 64] = leaper_attacks(&KING_OFFSETS);
+
+/// Built at compile time, for pieces that jump straight to their target squares
+const fn leaper_attacks(offsets: &[[i8; 2]; 8]) -> [u64; 64] {
+    let mut attacks = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            let file = (sq % 8) as i8 + offsets[i][0];
+            let rank = (sq / 8) as i8 + offsets[i][1];
+            if file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+                attacks[sq] |= 1 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    attacks
+}
+
+/// Where every piece is, for working out what attacks what. The chessboard keeps one of these up to
+/// date as pieces are added and removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    /// Indexed by `side_idx`, then `kind_idx`
+    pub pieces: [[u64; 6]; 2],
+    pub occupied_by: [u64; 2],
+    pub occupied: u64,
+}
+
+impl Bitboards {
+    pub fn new(pieces: &HashMap<[u8; 2], Piece>) -> Bitboards {
+        let mut bitboards = Bitboards::default();
+        for (&pos, piece) in pieces {
+            bitboards.toggle(pos, piece);
+        }
+        bitboards
+    }
+
+    /// Adds `piece` at `pos`, or takes it out if it's already there
+    #[inline(always)]
+    pub fn toggle(&mut self, pos: [u8; 2], piece: &Piece) {
+        let bit = square_bit(pos);
+        let side = side_idx(piece.data().side);
+        self.pieces[side][kind_idx(piece)] ^= bit;
+        self.occupied_by[side] ^= bit;
+        self.occupied ^= bit;
+    }
+
+    /// Every piece of `side` that attacks `sq`, as if only the squares in `occupied` had pieces on
+    /// them. Taking pieces out of `occupied` uncovers the sliders behind them.
+    pub fn attackers_to(&self, sq: usize, side: Side, occupied: u64) -> u64 {
+        let pieces = &self.pieces[side_idx(side)];
+        let diagonal = pieces[BISHOP] | pieces[QUEEN];
+        let straight = pieces[ROOK] | pieces[QUEEN];
+        // a pawn of `side` attacks `sq` from wherever the other side's pawn on `sq` would attack
+        let attackers = pawn_attacks(1 << sq, side.other()) & pieces[PAWN]
+            | KNIGHT_ATTACKS[sq] & pieces[KNIGHT]
+            | bishop_attacks(sq, occupied) & diagonal
+            | rook_attacks(sq, occupied) & straight
+            | KING_ATTACKS[sq] & pieces[KING];
+        attackers & occupied
+    }
+
+    /// Every square attacked by one of `side`'s pieces, including squares with its own pieces on
+    /// them
+    pub fn attacked_by(&self, side: Side) -> u64 {
+        let pieces = &self.pieces[side_idx(side)];
+        let mut attacked = pawn_attacks(pieces[PAWN], side);
+        for (kind, &bitboard) in pieces.iter().enumerate().skip(KNIGHT) {
+            for sq in bits(bitboard) {
+                attacked |= piece_attacks(kind, sq, self.occupied);
+            }
+        }
+        attacked
+    }
+}
+
+/// The squares a piece of `kind` on `sq` attacks. Pawns aren't handled here, since their attacks
+/// depend on their side, and it's faster to do them all at once with `pawn_attacks`.
+pub fn piece_attacks(kind: usize, sq: usize, occupied: u64) -> u64 {
+    match kind {
+        KNIGHT => KNIGHT_ATTACKS[sq],
+        BISHOP => bishop_attacks(sq, occupied),
+        ROOK => rook_attacks(sq, occupied),
+        QUEEN => queen_attacks(sq, occupied),
+        KING => KING_ATTACKS[sq],
+        _ => 0,
+    }
+}
+
+/// The squares strictly between `a` and `b`, if they're on the same rank, file or diagonal
+pub fn between(a: usize, b: usize) -> u64 {
+    let (a_bit, b_bit) = (1 << a, 1 << b);
+    if bishop_attacks(a, 0) & b_bit != 0 {
+        bishop_attacks(a, b_bit) & bishop_attacks(b, a_bit)
+    } else if rook_attacks(a, 0) & b_bit != 0 {
+        rook_attacks(a, b_bit) & rook_attacks(b, a_bit)
+    } else {
+        0
+    }
+}
+
+#[inline(always)]
+pub fn square_bit(pos: [u8; 2]) -> u64 {
+    1 << square_idx(pos)
+}
+
+/// The indices of the bits set in `bitboard`
+pub fn bits(mut bitboard: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            None
+        } else {
+            let sq = bitboard.trailing_zeros() as usize;
+            bitboard &= bitboard - 1;
+            Some(sq)
+        }
+    })
+}
+
+#[inline(always)]
+pub fn file_mask(file: usize) -> u64 {
+    FILE_A << file
+}
+
+#[inline(always)]
+pub fn rank_mask(rank: usize) -> u64 {
+    0xFF << (rank * 8)
+}
+
+pub fn adjacent_files(file: usize) -> u64 {
+    let left = if file > 0 { file_mask(file - 1) } else { 0 };
+    let right = if file < 7 { file_mask(file + 1) } else { 0 };
+    left | right
+}
+
+/// Every square attacked by one of `side`'s `pawns`
+pub fn pawn_attacks(pawns: u64, side: Side) -> u64 {
+    match side {
+        Side::Light => (pawns & !FILE_A) << 7 | (pawns & !FILE_H) << 9,
+        Side::Dark => (pawns & !FILE_A) >> 9 | (pawns & !FILE_H) >> 7,
+    }
+}
+
+pub fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
+    slider_attacks(sq, occupied, &BISHOP_DIRECTIONS)
+}
+
+pub fn rook_attacks(sq: usize, occupied: u64) -> u64 {
+    slider_attacks(sq, occupied, &ROOK_DIRECTIONS)
+}
+
+pub fn queen_attacks(sq: usize, occupied: u64) -> u64 {
+    bishop_attacks(sq, occupied) | rook_attacks(sq, occupied)
+}
+
+/// Follows each direction until it runs off the board, or into a piece (which is attacked too).
+/// This isn't as fast as magic bitboards, but it's much simpler, and fast enough for evaluation.
+fn slider_attacks(sq: usize, occupied: u64, directions: &[[i8; 2]; 4]) -> u64 {
+    let mut attacks = 0;
+    for [dx, dy] in directions {
+        let (mut file, mut rank) = ((sq % 8) as i8, (sq / 8) as i8);
+        loop {
+            file += dx;
+            rank += dy;
+            if !(0..8).contains(&file) || !(0..8).contains(&rank) {
+                break;
+            }
+            let bit = 1 << (rank * 8 + file);
+            attacks |= bit;
+            if occupied & bit != 0 {
+                break;
+            }
+        }
+    }
+    attacks
+}
@@ -1,4 +1,8 @@
-use crate::piece::{MoveType, Piece, PieceData, Side};
+use crate::attacks::square_pos;
+use crate::bitboard::{bits, Bitboards};
+use crate::nnue::{self, Accumulator};
+use crate::piece::{side_idx, MoveType, Piece, PieceData, Side};
+use crate::table::{castle_key, piece_key, turn_key};
 use crate::BOARD_SIZE;
 use std::collections::HashMap;
 
@@ -139,13 +143,28 @@ impl CastleRights {
     }
 }
 
+/// The FEN of the standard starting position
+pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 #[derive(Clone)]
 pub struct Chessboard {
-    pub pieces: HashMap<[u8; 2], Piece>,
+    pieces: HashMap<[u8; 2], Piece>,
+    /// The position of the pawn that just made a double step, and can be captured en passant
     pub en_passant: Option<[u8; 2]>,
     pub turn: Side,
     light_castle: CastleRights,
     dark_castle: CastleRights,
+    /// The Zobrist hash of the position, updated every time the board changes
+    hash: u64,
+    /// The part of `hash` that came from the en passant square. Whether the en passant square is
+    /// hashed depends on the pawns next to it, which may have moved by the time it's cleared.
+    en_passant_hash: u64,
+    /// A Zobrist hash of just the pawns, so the pawn structure evaluation can be cached
+    pawn_hash: u64,
+    /// Where each kind of piece is, updated every time the board changes
+    bitboards: Bitboards,
+    /// The NNUE accumulator for this position, if a network was loaded when the board was set up
+    accumulator: Option<Accumulator>,
 }
 
 pub fn create_piece(
@@ -158,13 +177,8 @@ pub fn create_piece(
     pieces.insert(pos, piece_type(data));
 }
 
-fn str_to_pos(s: &str) -> [u8; 2] {
-    let s = s.to_ascii_uppercase();
-    let s = s.as_bytes();
-    [s[0] - b'A', s[1] - b'1']
-}
-
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum Checkmate {
     Nothing,
     Checkmate,
@@ -172,149 +186,168 @@ pub enum Checkmate {
 }
 
 impl Chessboard {
-    /// Creates a new chessboard with the standard arrangement of pieces
-    pub fn standard() -> Chessboard {
-        let mut pieces = HashMap::with_capacity(32);
-
-        create_piece(&mut pieces, str_to_pos("a1"), Side::Light, &Piece::Rook);
-        create_piece(&mut pieces, str_to_pos("b1"), Side::Light, &Piece::Knight);
-        create_piece(&mut pieces, str_to_pos("c1"), Side::Light, &Piece::Bishop);
-        create_piece(&mut pieces, str_to_pos("d1"), Side::Light, &Piece::Queen);
-        create_piece(&mut pieces, str_to_pos("e1"), Side::Light, &Piece::King);
-        create_piece(&mut pieces, str_to_pos("f1"), Side::Light, &Piece::Bishop);
-        create_piece(&mut pieces, str_to_pos("g1"), Side::Light, &Piece::Knight);
-        create_piece(&mut pieces, str_to_pos("h1"), Side::Light, &Piece::Rook);
-
-        create_piece(&mut pieces, str_to_pos("a8"), Side::Dark, &Piece::Rook);
-        create_piece(&mut pieces, str_to_pos("b8"), Side::Dark, &Piece::Knight);
-        create_piece(&mut pieces, str_to_pos("c8"), Side::Dark, &Piece::Bishop);
-        create_piece(&mut pieces, str_to_pos("d8"), Side::Dark, &Piece::Queen);
-        create_piece(&mut pieces, str_to_pos("e8"), Side::Dark, &Piece::King);
-        create_piece(&mut pieces, str_to_pos("f8"), Side::Dark, &Piece::Bishop);
-        create_piece(&mut pieces, str_to_pos("g8"), Side::Dark, &Piece::Knight);
-        create_piece(&mut pieces, str_to_pos("h8"), Side::Dark, &Piece::Rook);
-
-        // Create pawns
-        for file in 0..8u8 {
-            create_piece(&mut pieces, [file, 1], Side::Light, &Piece::Pawn);
-            create_piece(&mut pieces, [file, 6], Side::Dark, &Piece::Pawn);
-        }
-
-        Chessboard {
-            pieces,
-            en_passant: None,
-            turn: Side::Light,
-            light_castle: CastleRights::Both,
-            dark_castle: CastleRights::Both,
-        }
+    /// Sets up the position in `fen`, which has to be valid (see `try_from_fen`)
+    pub fn from_fen(fen: String) -> Self {
+        Chessboard::try_from_fen(&fen).unwrap_or_else(|e| panic!("Invalid FEN {}: {}", fen, e))
     }
 
-    pub fn from_fen(fen: String) -> Self {
+    /// Sets up the position in `fen`, or says what's wrong with it. The halfmove and fullmove
+    /// clocks may be left off, as they are in EPD files.
+    pub fn try_from_fen(fen: &str) -> Result<Chessboard, String> {
         // TODO: Halfmove and fullmove clocks
-        if let [board, turn, castle, ep, _, _] = fen.split(' ').collect::<Vec<_>>()[..] {
-            let mut pieces = HashMap::with_capacity(32);
-
-            let mut rank = 7u8; // ranks 1-8 correspond to numbers 1-7
-            let mut file = 0u8; // files a-h correspond to 1-7
-            for c in board.chars() {
-                // if it's a number, we have that many whitespaces
-                if let Some(empties) = c.to_digit(10) {
-                    file += empties as u8;
-                } else if c == '/' {
-                    rank -= 1u8;
-                    file = 0u8;
-                } else {
-                    match c {
-                        'r' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::Rook);
-                            file += 1;
-                        }
-                        'n' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::Knight);
-                            file += 1;
-                        }
-                        'b' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::Bishop);
-                            file += 1;
-                        }
-                        'q' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::Queen);
-                            file += 1;
-                        }
-                        'k' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::King);
-                            file += 1;
-                        }
-                        'p' => {
-                            create_piece(&mut pieces, [file, rank], Side::Dark, &Piece::Pawn);
-                            file += 1;
-                        }
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let (board, turn, castle, ep) = match fields[..] {
+            [board, turn, castle, ep] | [board, turn, castle, ep, _, _] => (board, turn, castle, ep),
+            _ => return Err(format!("has {} fields instead of 6", fields.len())),
+        };
 
-                        'R' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::Rook);
-                            file += 1;
-                        }
-                        'N' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::Knight);
-                            file += 1;
-                        }
-                        'B' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::Bishop);
-                            file += 1;
-                        }
-                        'Q' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::Queen);
-                            file += 1;
-                        }
-                        'K' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::King);
-                            file += 1;
-                        }
-                        'P' => {
-                            create_piece(&mut pieces, [file, rank], Side::Light, &Piece::Pawn);
-                            file += 1;
-                        }
-                        _ => panic!("Invalid FEN"),
-                    }
+        let mut pieces = HashMap::with_capacity(32);
+        let ranks: Vec<&str> = board.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("has {} ranks instead of 8", ranks.len()));
+        }
+        // the ranks are listed from the 8th down to the 1st
+        for (rank, row) in (0..8u8).rev().zip(ranks) {
+            let mut file = 0u8;
+            for c in row.chars() {
+                // if it's a number, we have that many empty squares
+                if let Some(empties @ 1..=8) = c.to_digit(10) {
+                    file += empties as u8;
+                    continue;
+                }
+                let piece_type: &dyn Fn(PieceData) -> Piece = match c.to_ascii_lowercase() {
+                    'p' => &Piece::Pawn,
+                    'n' => &Piece::Knight,
+                    'b' => &Piece::Bishop,
+                    'r' => &Piece::Rook,
+                    'q' => &Piece::Queen,
+                    'k' => &Piece::King,
+                    _ => return Err(format!("{} isn't a piece", c)),
+                };
+                if file >= 8 {
+                    return Err(format!("rank {} has more than 8 squares", rank + 1));
                 }
+                let side = if c.is_ascii_uppercase() { Side::Light } else { Side::Dark };
+                create_piece(&mut pieces, [file, rank], side, piece_type);
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("rank {} doesn't have 8 squares", rank + 1));
+            }
+        }
+        // the search assumes both kings are always there
+        for side in [Side::Light, Side::Dark] {
+            let kings = pieces
+                .values()
+                .filter(|piece| matches!(piece, Piece::King(data) if data.side == side))
+                .count();
+            if kings != 1 {
+                return Err(format!("has {} {:?} kings", kings, side));
+            }
+        }
+
+        let turn = match turn {
+            "w" => Side::Light,
+            "b" => Side::Dark,
+            _ => return Err(format!("{} isn't a side to move", turn)),
+        };
+
+        let mut light_castle = CastleRights::NoRights;
+        let mut dark_castle = CastleRights::NoRights;
+
+        for c in castle.chars() {
+            match c {
+                '-' => break,
+                'K' => light_castle = light_castle.add_right(CastleRights::KingSide),
+                'Q' => light_castle = light_castle.add_right(CastleRights::QueenSide),
+                'k' => dark_castle = dark_castle.add_right(CastleRights::KingSide),
+                'q' => dark_castle = dark_castle.add_right(CastleRights::QueenSide),
+                _ => return Err(format!("{} isn't a castling right", c)),
             }
+        }
 
-            let turn = match turn {
-                "w" => Side::Light,
-                "b" => Side::Dark,
-                _ => panic!("Invalid FEN Entered"),
-            };
-
-            let mut light_castle = CastleRights::NoRights;
-            let mut dark_castle = CastleRights::NoRights;
-
-            for c in castle.chars() {
-                match c {
-                    '-' => break,
-                    'K' => light_castle = light_castle.add_right(CastleRights::KingSide),
-                    'Q' => light_castle = light_castle.add_right(CastleRights::QueenSide),
-                    'k' => dark_castle = dark_castle.add_right(CastleRights::KingSide),
-                    'q' => dark_castle = dark_castle.add_right(CastleRights::QueenSide),
-                    _ => panic!("Invalid FEN"),
+        // FEN gives the square behind the pawn, but we keep track of the pawn itself
+        let ep = match ep.as_bytes() {
+            b"-" => None,
+            [file @ b'a'..=b'h', b'3'] => Some([file - b'a', 3]),
+            [file @ b'a'..=b'h', b'6'] => Some([file - b'a', 4]),
+            _ => return Err(format!("{} isn't an en passant square", ep)),
+        };
+
+        // Castling rights without the king and rook on their starting squares, and an en passant
+        // square without a pawn that's just moved in front of it, can't be used, so they're
+        // dropped rather than trusted
+        let at_home = |pos: [u8; 2], side: Side, is_kind: fn(&Piece) -> bool| {
+            pieces
+                .get(&pos)
+                .is_some_and(|piece| piece.data().side == side && is_kind(piece))
+        };
+        for (side, rights) in [(Side::Light, &mut light_castle), (Side::Dark, &mut dark_castle)] {
+            for right in [CastleRights::KingSide, CastleRights::QueenSide] {
+                let rook_pos = right.rook_init_pos(side)?;
+                if !at_home([4, side.back_rank()], side, |p| matches!(p, Piece::King(_)))
+                    || !at_home(rook_pos, side, |p| matches!(p, Piece::Rook(_)))
+                {
+                    *rights = rights.remove_rights(right);
                 }
             }
+        }
+        let ep = ep.filter(|&pos| at_home(pos, turn.other(), |p| matches!(p, Piece::Pawn(_))));
 
-            let ep = if ep == "-" {
-                None
-            } else {
-                Some(str_to_pos(ep))
-            };
-
-            Chessboard {
-                pieces,
-                en_passant: ep,
-                turn: turn,
-                light_castle: light_castle,
-                dark_castle: dark_castle,
-            }
-        } else {
-            panic!("Invalid FEN Entered")
+        Ok(Chessboard {
+            pieces,
+            en_passant: ep,
+            turn,
+            light_castle,
+            dark_castle,
+            hash: 0,
+            en_passant_hash: 0,
+            pawn_hash: 0,
+            bitboards: Bitboards::default(),
+            accumulator: None,
         }
+        .with_hash())
+    }
+
+    /// Fills in the hash (as well as the bitboards and NNUE accumulator) of a newly created
+    /// chessboard
+    fn with_hash(mut self) -> Chessboard {
+        self.en_passant_hash = self.en_passant_key(self.en_passant);
+        self.hash = self.zobrist_hash();
+        self.pawn_hash = self.pawn_zobrist_hash();
+        self.bitboards = Bitboards::new(&self.pieces);
+        self.accumulator = self.fresh_accumulator();
+        self
+    }
+
+    /// The NNUE accumulator, kept up to date as pieces move, if there was a network when the board
+    /// was set up
+    #[inline(always)]
+    pub fn accumulator(&self) -> Option<&Accumulator> {
+        self.accumulator.as_ref()
+    }
+
+    fn fresh_accumulator(&self) -> Option<Accumulator> {
+        nnue::network().map(|network| Accumulator::new(network, &self.pieces))
+    }
+
+    /// Whether the accumulator matches one computed from scratch (or there isn't one)
+    fn accumulator_is_fresh(&self) -> bool {
+        self.accumulator.is_none() || self.accumulator == self.fresh_accumulator()
+    }
+
+    /// The Zobrist hash of the position, including the side to move, castling rights, and en
+    /// passant square. This is the position's Polyglot key, so it's the same in every run, and
+    /// can be looked up in Polyglot opening books.
+    #[inline(always)]
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The Zobrist hash of only the pawns on the board
+    #[inline(always)]
+    pub fn pawn_hash(&self) -> u64 {
+        self.pawn_hash
     }
 
     pub fn castle_rights(&self, side: Side) -> CastleRights {
@@ -341,46 +374,100 @@ impl Chessboard {
         &self.pieces
     }
 
+    #[inline(always)]
+    pub fn bitboards(&self) -> &Bitboards {
+        &self.bitboards
+    }
+
     /// A wrapper on HashMap::insert, which just inserts the piece into the hashmap without any
     /// checks, and returns the piece it might have replaced.
     pub fn insert(&mut self, pos: [u8; 2], piece: Piece) -> Option<Piece> {
-        self.pieces.insert(pos, piece)
+        self.toggle_piece(pos, &piece);
+        self.update_accumulator(pos, &piece, true);
+        let replaced = self.pieces.insert(pos, piece);
+        if let Some(replaced) = &replaced {
+            self.toggle_piece(pos, replaced);
+            self.update_accumulator(pos, replaced, false);
+        }
+        replaced
     }
 
-    fn remove_all_castle_rights(&mut self, side: Side) {
-        match side {
-            Side::Light => self.light_castle = self.light_castle.remove_rights(CastleRights::Both),
-            Side::Dark => self.dark_castle = self.dark_castle.remove_rights(CastleRights::Both),
+    /// A wrapper on HashMap::remove, which keeps the hash up to date.
+    pub fn remove(&mut self, pos: [u8; 2]) -> Option<Piece> {
+        let removed = self.pieces.remove(&pos);
+        if let Some(removed) = &removed {
+            self.toggle_piece(pos, removed);
+            self.update_accumulator(pos, removed, false);
+        }
+        removed
+    }
+
+    /// Adds `piece` at `pos` to the NNUE accumulator, or takes it out
+    fn update_accumulator(&mut self, pos: [u8; 2], piece: &Piece, added: bool) {
+        if let (Some(accumulator), Some(network)) = (&mut self.accumulator, nnue::network()) {
+            if added {
+                accumulator.add(network, pos, piece);
+            } else {
+                accumulator.remove(network, pos, piece);
+            }
+        }
+    }
+
+    /// Adds `piece` at `pos` to the hashes and bitboards, or takes it out if it was already in them
+    fn toggle_piece(&mut self, pos: [u8; 2], piece: &Piece) {
+        let key = piece_key(pos, piece);
+        self.hash ^= key;
+        if let Piece::Pawn(_) = piece {
+            self.pawn_hash ^= key;
         }
+        self.bitboards.toggle(pos, piece);
+    }
+
+    fn set_en_passant(&mut self, en_passant: Option<[u8; 2]>) {
+        self.hash ^= self.en_passant_hash;
+        self.en_passant = en_passant;
+        self.en_passant_hash = self.en_passant_key(en_passant);
+        self.hash ^= self.en_passant_hash;
+    }
+
+    fn switch_turn(&mut self) {
+        self.hash ^= turn_key(self.turn);
+        self.turn = self.turn.other();
+        self.hash ^= turn_key(self.turn);
+    }
+
+    fn remove_all_castle_rights(&mut self, side: Side) {
+        self.set_castle_rights(side, CastleRights::NoRights);
     }
 
     fn set_castle_rights(&mut self, side: Side, rights: CastleRights) {
+        self.hash ^= castle_key(side, self.castle_rights(side));
         match side {
             Side::Light => self.light_castle = rights,
             Side::Dark => self.dark_castle = rights,
         }
+        self.hash ^= castle_key(side, rights);
     }
 
     pub fn is_checkmated(&self, side: Side) -> Checkmate {
-        //println!("{:?}", side);
-        for piece in self.pieces.values() {
-            if piece.data().side != side {
-                continue;
-            }
-            for i in 0..8 {
-                for j in 0..8 {
-                    // I'm making the assumption that it doesn't matter what you promote to.
-                    // If you're going to get out of check by pawn promotion, you're either
-                    //     - capturing the checking piece
-                    //     - blocking
-                    //  In either scenario, it doesn't matter what you promote to
-                    let move_type = piece.can_move(self, [i, j], true, Some(&Piece::Queen));
-                    if move_type != MoveType::Invalid {
-                        //println!("{:?} to {:?} is {:?}", piece, [i, j], move_type);
-                        return Checkmate::Nothing;
-                    }
-                }
-            }
+        // The same moves as `possible_moves`, but stopping at the first one.
+        // I'm making the assumption that it doesn't matter what you promote to.
+        // If you're going to get out of check by pawn promotion, you're either
+        //     - capturing the checking piece
+        //     - blocking
+        //  In either scenario, it doesn't matter what you promote to
+        let has_move = self
+            .pieces
+            .values()
+            .filter(|piece| piece.data().side == side)
+            .any(|piece| {
+                bits(self.candidate_targets(piece)).any(|sq| {
+                    piece.can_move(self, square_pos(sq), true, Some(&Piece::Queen))
+                        != MoveType::Invalid
+                })
+            });
+        if has_move {
+            return Checkmate::Nothing;
         }
         if self.is_side_in_check(side) {
             Checkmate::Checkmate
@@ -395,16 +482,26 @@ impl Chessboard {
         move_type: MoveType,
         end_pos: [u8; 2],
         promotion: Option<&dyn Fn(PieceData) -> Piece>,
-    ) -> MoveResult {
+    ) -> MoveResult<'_> {
         // this function used to work on the assumption that the piece was removed from the pieces
         // map, and that this function's responsibility was to add it back in. However, when we are
         // looking at checkmate, that's not the case. So, enter, this ugly hack
-        self.pieces.remove(&piece.data().position); // if this is None, that means it was already handled. Otherwise, rmeove it.
-        match move_type {
-            MoveType::Invalid => {
-                self.insert(piece.data().position, piece);
-                MoveResult::Invalid
-            }
+        self.remove(piece.data().position); // if this is None, that means it was already handled. Otherwise, rmeove it.
+        if move_type == MoveType::Invalid {
+            self.insert(piece.data().position, piece);
+            debug_assert_eq!(self.hash, self.zobrist_hash());
+            debug_assert_eq!(self.pawn_hash, self.pawn_zobrist_hash());
+            debug_assert!(self.accumulator_is_fresh());
+            debug_assert_eq!(self.bitboards, Bitboards::new(&self.pieces));
+            return MoveResult::Invalid;
+        }
+
+        // Every move clears the en passant square, although a doublestep sets a new one.
+        let en_passant = self.en_passant;
+        self.set_en_passant(None);
+
+        let result = match move_type {
+            MoveType::Invalid => unreachable!(),
 
             MoveType::Capture => {
                 match &piece {
@@ -425,8 +522,8 @@ impl Chessboard {
                 };
                 piece.data_mut().position = end_pos;
                 let captured = self.insert(end_pos, piece).unwrap();
-                self.en_passant = None;
-                self.turn = self.turn.other();
+                self.remove_captured_rook_rights(&captured);
+                self.switch_turn();
                 MoveResult::Capture {
                     moved: self.piece_at(end_pos).unwrap(),
                     captured,
@@ -451,26 +548,24 @@ impl Chessboard {
                     _ => {}
                 };
                 piece.data_mut().position = end_pos;
-                self.en_passant = None;
-                self.turn = self.turn.other();
+                self.switch_turn();
                 self.insert(end_pos, piece);
                 MoveResult::Regular(self.piece_at(end_pos).unwrap())
             }
 
             MoveType::Doublestep => {
                 piece.data_mut().position = end_pos;
-                self.en_passant = Some(piece.data().position);
-                self.turn = self.turn.other();
+                self.switch_turn();
                 self.insert(end_pos, piece);
+                self.set_en_passant(Some(end_pos));
                 MoveResult::Regular(self.piece_at(end_pos).unwrap())
             }
 
             MoveType::EnPassant => {
                 piece.data_mut().position = end_pos;
                 self.insert(end_pos, piece); // this should not return anything
-                let captured = self.pieces.remove(&self.en_passant.unwrap()).unwrap();
-                self.en_passant = None;
-                self.turn = self.turn.other();
+                let captured = self.remove(en_passant.unwrap()).unwrap();
+                self.switch_turn();
                 MoveResult::EnPassant {
                     moved: self.piece_at(end_pos).unwrap(),
                     captured,
@@ -485,15 +580,14 @@ impl Chessboard {
                 // move the king
                 piece.data_mut().position = end_pos;
 
-                let mut rook = self.pieces.remove(&rook_pos).unwrap();
+                let mut rook = self.remove(rook_pos).unwrap();
                 let rook_end_pos = castle_type.rook_final_pos(piece.data().side).unwrap();
                 rook.data_mut().position = rook_end_pos;
-                self.en_passant = None;
                 // we can only castle once
                 self.remove_all_castle_rights(piece.data().side);
                 self.insert(rook_end_pos, rook);
                 self.insert(end_pos, piece);
-                self.turn = self.turn.other();
+                self.switch_turn();
                 MoveResult::Castle {
                     king: self.piece_at(end_pos).unwrap(),
                     rook: self.piece_at(rook_end_pos).unwrap(),
@@ -509,9 +603,8 @@ impl Chessboard {
                     );
                 }
                 piece.data_mut().position = end_pos;
-                self.en_passant = None;
                 self.insert(end_pos, piece);
-                self.turn = self.turn.other();
+                self.switch_turn();
                 MoveResult::PawnPromotion(self.piece_at(end_pos).unwrap())
             }
 
@@ -523,13 +616,31 @@ impl Chessboard {
                 }
                 piece.data_mut().position = end_pos;
                 let captured = self.insert(end_pos, piece).unwrap();
-                self.en_passant = None;
-                self.turn = self.turn.other();
+                self.remove_captured_rook_rights(&captured);
+                self.switch_turn();
                 MoveResult::PawnPromotionCapture {
                     moved: self.piece_at(end_pos).unwrap(),
                     captured,
                 }
             }
+        };
+
+        debug_assert_eq!(self.hash, self.zobrist_hash());
+        debug_assert_eq!(self.pawn_hash, self.pawn_zobrist_hash());
+        debug_assert!(self.accumulator_is_fresh());
+        debug_assert_eq!(self.bitboards, Bitboards::new(&self.pieces));
+        result
+    }
+
+    /// A rook that gets captured before it moves takes its side's castling rights with it.
+    fn remove_captured_rook_rights(&mut self, captured: &Piece) {
+        if let Piece::Rook(data) = captured {
+            if let Ok(rights) = CastleRights::castle_rights_for_rook(captured) {
+                self.set_castle_rights(
+                    data.side,
+                    self.castle_rights(data.side).remove_rights(rights),
+                );
+            }
         }
     }
 
@@ -538,9 +649,9 @@ impl Chessboard {
         piece_ref: &Piece,
         end_pos: [u8; 2],
         promotion: Option<&dyn Fn(PieceData) -> Piece>,
-    ) -> MoveResult {
+    ) -> MoveResult<'_> {
         // get the copy in the hashset. We can't be certain that piece_ref references the hashset.
-        let piece = self.pieces.remove(&piece_ref.data().position).unwrap();
+        let piece = self.remove(piece_ref.data().position).unwrap();
 
         let move_type = if piece.data().side != self.turn {
             // if it's not your turn, it doesn't matter, it's already invalid.
@@ -552,15 +663,26 @@ impl Chessboard {
         self.apply_move(piece, move_type, end_pos, promotion)
     }
 
-    /// This iterates through the entire HashMap to find the king.
-    /// There must be a better way
-    pub fn king(&self, side: Side) -> Option<&Piece> {
-        self.pieces.values().find(|piece| match piece {
-            Piece::King(data) if data.side == side => true,
-            _ => false,
+    /// Passes the turn to the other side without moving anything. This isn't a legal move, but
+    /// null move pruning needs it.
+    pub fn make_null_move(&mut self) {
+        self.set_en_passant(None);
+        self.switch_turn();
+    }
+
+    /// Returns true if `side` has anything besides pawns and its king. Without any, zugzwang
+    /// becomes likely.
+    pub fn has_non_pawn_material(&self, side: Side) -> bool {
+        self.pieces.values().any(|piece| {
+            piece.data().side == side && !matches!(piece, Piece::Pawn(_) | Piece::King(_))
         })
     }
 
+    pub fn king(&self, side: Side) -> Option<&Piece> {
+        self.king_square(side)
+            .and_then(|sq| self.piece_at(square_pos(sq)))
+    }
+
     // TODO: Make this return an iterator so we don't actually calculate everything if we don't
     // need to.
     pub fn possible_moves(&self, side: Side) -> Vec<(&Piece, [u8; 2])> {
@@ -570,12 +692,11 @@ impl Chessboard {
             if piece.data().side != side {
                 continue;
             }
-            for i in 0..8 {
-                for j in 0..8 {
-                    let move_type = piece.can_move(self, [i, j], true, Some(&Piece::Queen));
-                    if move_type != MoveType::Invalid {
-                        moves.push((piece, [i, j]));
-                    }
+            for sq in bits(self.candidate_targets(piece)) {
+                let end_pos = square_pos(sq);
+                let move_type = piece.can_move(self, end_pos, true, Some(&Piece::Queen));
+                if move_type != MoveType::Invalid {
+                    moves.push((piece, end_pos));
                 }
             }
         }
@@ -583,32 +704,40 @@ impl Chessboard {
         moves
     }
 
-    pub fn is_king_in_check(&self, king: &Piece) -> bool {
-        for (_pos, piece) in self.pieces.iter() {
-            if piece.data().side == king.data().side {
+    /// Like `possible_moves`, but only the moves that change the material on the board: captures
+    /// (including en passant) and promotions. This is what quiescence search looks at.
+    pub fn possible_captures(&self, side: Side) -> Vec<(&Piece, [u8; 2])> {
+        let mut moves = Vec::new();
+
+        for piece in self.pieces.values() {
+            if piece.data().side != side {
                 continue;
             }
-            match piece {
-                Piece::Pawn(_data)
-                | Piece::Rook(_data)
-                | Piece::Knight(_data)
-                | Piece::Bishop(_data)
-                | Piece::Queen(_data)
-                | Piece::King(_data) => {
-                    // NOTE: If you must promote, "promote" to a pawn.
-                    // This refers to where the pawn directly threatens the
-                    // king.
-                    // Therefore, what it promotes to doesn't matter.
-                    // To emphasize this, we "promote" to a pawn.
-                    let move_type =
-                        piece.can_move(self, king.data().position, false, Some(&Piece::Pawn));
-                    if let MoveType::Capture | MoveType::PawnPromotionCapture = move_type {
-                        return true;
-                    }
+            let mut targets = self.candidate_targets(piece);
+            // Only pawns can make a material-changing move to an empty square, so we can skip
+            // calling can_move for everything else.
+            if !matches!(piece, Piece::Pawn(_)) {
+                targets &= self.bitboards().occupied_by[side_idx(side.other())];
+            }
+            for sq in bits(targets) {
+                let end_pos = square_pos(sq);
+                let move_type = piece.can_move(self, end_pos, true, Some(&Piece::Queen));
+                if let MoveType::Capture
+                | MoveType::EnPassant
+                | MoveType::PawnPromotion
+                | MoveType::PawnPromotionCapture = move_type
+                {
+                    moves.push((piece, end_pos));
                 }
             }
         }
-        false
+
+        moves
+    }
+
+    pub fn is_king_in_check(&self, king: &Piece) -> bool {
+        let data = king.data();
+        self.is_attacked(data.position, data.side.other())
     }
 
     pub fn is_side_in_check(&self, side: Side) -> bool {
@@ -616,3 +745,69 @@ impl Chessboard {
         self.is_king_in_check(king)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many move sequences `depth` plies long there are from `chessboard`
+    fn perft(chessboard: &Chessboard, depth: u32) -> u64 {
+        let moves = chessboard.possible_moves(chessboard.turn);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .into_iter()
+            .map(|(piece, end_pos)| {
+                let mut temp = chessboard.clone();
+                temp.try_move(piece, end_pos, Some(&Piece::Queen));
+                perft(&temp, depth - 1)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn perft_counts() {
+        // The well-known perft positions, only as deep as they go without any promotions, since
+        // we only generate promotions to a queen. The last one's published count is 44, with the
+        // three underpromotions.
+        let positions = [
+            (STARTPOS, 4, 197_281),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2, 2039),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43_238),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 1, 41),
+        ];
+        for (fen, depth, nodes) in positions.iter() {
+            let chessboard = Chessboard::from_fen(String::from(*fen));
+            assert_eq!(perft(&chessboard, *depth), *nodes, "{} to depth {}", fen, depth);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_fens() {
+        let fens = [
+            "",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+            "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1",
+        ];
+        for fen in fens.iter() {
+            assert!(Chessboard::try_from_fen(fen).is_err(), "{:?} was accepted", fen);
+        }
+    }
+
+    #[test]
+    fn drops_unusable_rights() {
+        // the clocks are optional, the h1 rook has moved, and there's no pawn on e4
+        let chessboard =
+            Chessboard::try_from_fen("r3k2r/8/8/8/8/8/8/R3K1R1 b KQkq e3").unwrap();
+        assert_eq!(chessboard.castle_rights(Side::Light), CastleRights::QueenSide);
+        assert_eq!(chessboard.castle_rights(Side::Dark), CastleRights::Both);
+        assert_eq!(chessboard.en_passant, None);
+    }
+}
@@ -1,17 +1,41 @@
-use crate::ai;
-use crate::chessboard::{Checkmate, Chessboard, MoveResult};
-use crate::piece::{Piece, PieceData, Side};
-use crate::table::TranspositionTable;
 use crate::sidebar::Sidebar;
-use crate::{BOARD_BORDER_SIZE, BOARD_SIZE, HEIGHT};
+use crate::{BOARD_BORDER_SIZE, HEIGHT};
+use chess::ai;
+use chess::chessboard::{Checkmate, Chessboard, MoveResult};
+use chess::piece::{Piece, PieceData, Side};
+use chess::skill;
+use chess::table::{TranspositionTable, DEFAULT_TT_MEGABYTES};
+use chess::BOARD_SIZE;
 use drag_controller::{Drag, DragController};
 use graphics::Image;
-use piston::input::GenericEvent;
+use piston::input::{Button, GenericEvent, Key};
 use std::fmt::Write;
-use std::sync::{mpsc, Arc, RwLock};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
 use std::thread;
-
-static AI_LEVEL: u8 = 3;
+use std::time::Duration;
+
+const AI_LIMITS: ai::SearchLimits = ai::SearchLimits {
+    depth: None,
+    nodes: None,
+    movetime: Some(Duration::from_secs(2)),
+    clock: None,
+};
+const AI_OPTIONS: ai::SearchOptions = ai::SearchOptions {
+    null_move_pruning: true,
+    late_move_reductions: true,
+    futility_pruning: true,
+    reverse_futility_pruning: true,
+    check_extensions: true,
+    use_nnue: true,
+    // replaced by however many threads the machine can run at once
+    threads: 1,
+    // only raised while the candidate lines are shown, since every extra line costs search time
+    multi_pv: 1,
+    skill_level: skill::MAX_SKILL,
+};
+/// How many of the AI's candidate lines are shown, once they're turned on
+const AI_CANDIDATE_LINES: usize = 3;
 static AI_SIDE: Side = Side::Dark;
 static AI: bool = true;
 
@@ -102,15 +126,35 @@ pub struct ChessboardController {
     light_check: bool,
     dark_check: bool,
     pub game_result: (Checkmate, Side),
-    ai_rx: Option<mpsc::Receiver<(&'static Piece, [u8; 2])>>,
-    tt: Option<Arc<RwLock<TranspositionTable>>>,
+    ai_rx: Option<mpsc::Receiver<ai::SearchResult<'static>>>,
+    /// The score of the AI's last move, from its point of view
+    pub ai_score: Option<i32>,
+    /// The moves the AI was choosing between last time, best first
+    pub ai_lines: Vec<ai::PvLine>,
+    /// Whether the AI looks for (and the sidebar shows) several candidate lines, instead of just
+    /// the best one. Toggled with the L key, and takes effect from the AI's next move.
+    pub show_lines: bool,
+    /// How the AI searches, starting out as `AI_OPTIONS`
+    pub ai_options: ai::SearchOptions,
+    /// The squares of the side to move's pieces that the other side could win material by taking
+    pub hanging: Vec<[u8; 2]>,
+    /// Whether to show what the other side attacks, which pieces are pinned and which are giving
+    /// check. Toggled with the A key.
+    pub show_attacks: bool,
+    /// The squares the side that isn't moving attacks
+    pub attacked: u64,
+    /// The side to move's pinned pieces
+    pub pinned: u64,
+    /// The pieces giving check to the side to move
+    pub checkers: u64,
+    tt: Option<Arc<TranspositionTable>>,
     chessboard: Chessboard,
 }
 
 impl ChessboardController {
     pub fn new(chessboard: Chessboard) -> ChessboardController {
         let piece_rects = Vec::new();
-        ChessboardController {
+        let mut controller = ChessboardController {
             position: [BOARD_BORDER_SIZE; 2],
             size: HEIGHT - 2.0 * BOARD_BORDER_SIZE,
             piece_rects,
@@ -125,12 +169,34 @@ impl ChessboardController {
             // worth it
             game_result: (Checkmate::Nothing, Side::Light),
             ai_rx: None,
+            ai_score: None,
+            ai_lines: Vec::new(),
+            ai_options: ai::SearchOptions {
+                threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+                ..AI_OPTIONS
+            },
+            show_lines: false,
+            hanging: Vec::new(),
+            show_attacks: false,
+            attacked: 0,
+            pinned: 0,
+            checkers: 0,
             tt: match AI { 
-                true => Some(Arc::new(RwLock::new(TranspositionTable::new()))),
+                true => Some(Arc::new(TranspositionTable::new(DEFAULT_TT_MEGABYTES))),
                 false => None,
             },
             chessboard,
-        }
+        };
+        controller.update_attacks();
+        controller
+    }
+
+    /// Works out the attack overlay for the position on the board
+    fn update_attacks(&mut self) {
+        let turn = self.chessboard.turn;
+        self.attacked = self.chessboard.attacked_squares(turn.other());
+        self.pinned = self.chessboard.pinned_pieces(turn);
+        self.checkers = self.chessboard.checkers();
     }
 
     pub fn init_piece_rects(&mut self) {
@@ -263,11 +329,11 @@ impl ChessboardController {
         self.game_result = (self.chessboard.is_checkmated(side.other()), side);
         match self.game_result.0 {
             Checkmate::Checkmate => {
-                println!("Game Over! {:?} wins", self.game_result.1);
+                log::info!("Game Over! {:?} wins", self.game_result.1);
                 return;
             }
             Checkmate::Stalemate => {
-                println!("Game Over! Draw");
+                log::info!("Game Over! Draw");
                 return;
             }
             _ => {}
@@ -276,11 +342,13 @@ impl ChessboardController {
         self.light_check = self.chessboard.is_side_in_check(Side::Light);
         self.dark_check = self.chessboard.is_side_in_check(Side::Dark);
         if self.light_check {
-            println!("White in Check");
+            log::debug!("White in Check");
         }
         if self.dark_check {
-            println!("Black in Check");
+            log::debug!("Black in Check");
         }
+        self.hanging = self.chessboard.hanging_pieces(self.chessboard.turn);
+        self.update_attacks();
 
 
         //println!("{:?}", self.chessboard.zobrist_hash());
@@ -300,15 +368,30 @@ impl ChessboardController {
             // chess work.
             let chessboard = ChessboardPtr(&self.chessboard as *const Chessboard);
 
-            let tt = Arc::clone(&self.tt.as_ref().unwrap());
+            let tt = Arc::clone(self.tt.as_ref().unwrap());
+            let stop = Arc::new(AtomicBool::new(false));
+            let options = self.ai_options;
 
             //let best_move = rx.recv().unwrap();
             thread::spawn(move || {
                 let chessboard = unsafe { &(*chessboard.0) };
-                let best_move = ai::get_best_move(chessboard, AI_LEVEL, tt);
-                use ai::SimpleMove;
-                println!("Found best Move: {:?}", SimpleMove(best_move));
-                tx.send(best_move).unwrap();
+                let mut report = |report: &ai::SearchReport| log::info!("{}", report);
+                let result =
+                    ai::get_best_move(chessboard, &AI_LIMITS, &options, tt, stop, &mut report);
+                use ai::{Score, SimpleMove};
+                match result.best_move {
+                    Some(best_move) => {
+                        let pv: Vec<String> = result.pv().iter().map(|m| m.to_string()).collect();
+                        log::info!(
+                            "Found best Move: {:?} ({}), PV {}",
+                            SimpleMove(best_move),
+                            Score(result.score),
+                            pv.join(" ")
+                        );
+                    }
+                    None => log::info!("The AI has no moves"),
+                }
+                tx.send(result).unwrap();
             });
 
             self.ai_rx = Some(rx);
@@ -331,11 +414,15 @@ impl ChessboardController {
         if e.update_args().is_some() {
             if let Some(rx) = &self.ai_rx {
                 match rx.try_recv() {
-                    Ok(best_move) => {
-                        let piece = self.piece_idx_in_piece_rects(best_move.0);
-                        let pos = best_move.1;
-                        self.try_move(piece, pos, Some(&Piece::Queen));
+                    Ok(result) => {
+                        self.ai_score = Some(result.score);
+                        self.ai_lines = result.lines;
                         self.ai_rx = None;
+                        // the game is over if it has no moves, which `try_move` has already seen
+                        if let Some((piece, pos)) = result.best_move {
+                            let piece = self.piece_idx_in_piece_rects(piece);
+                            self.try_move(piece, pos, Some(&Piece::Queen));
+                        }
                     }
                     Err(mpsc::TryRecvError::Empty) => { /* nothing's happened, keep going */ }
                     Err(mpsc::TryRecvError::Disconnected) => panic!("AI disconnected"),
@@ -344,6 +431,14 @@ impl ChessboardController {
             }
         }
 
+        if let Some(Button::Keyboard(Key::A)) = e.press_args() {
+            self.show_attacks = !self.show_attacks;
+        }
+        if let Some(Button::Keyboard(Key::L)) = e.press_args() {
+            self.show_lines = !self.show_lines;
+            self.ai_options.multi_pv = if self.show_lines { AI_CANDIDATE_LINES } else { 1 };
+        }
+
         // if there is an AI currently running, the player cannot interact with the pieces.
         // otherwise, the unsafe used to a get an immutable reference to the chessboard is
         // actually unsafe. This has to be separate, because it applies to all events, not just
@@ -507,18 +602,18 @@ impl From<[f64; 4]> for Rectangle {
     }
 }
 
-impl Into<[f64; 4]> for Rectangle {
+impl From<Rectangle> for [f64; 4] {
     #[inline(always)]
-    fn into(self) -> [f64; 4] {
-        [self.x, self.y, self.w, self.h]
+    fn from(r: Rectangle) -> Self {
+        [r.x, r.y, r.w, r.h]
     }
 }
 
 // TODO: I don't know if this makes any sort of sense
 // I also don't know why we're using references for a Copy type. Maybe past me knows.
-impl Into<Image> for &Rectangle {
+impl From<&Rectangle> for Image {
     #[inline(always)]
-    fn into(self) -> Image {
-        Image::new().rect([self.x, self.y, self.w, self.h])
+    fn from(r: &Rectangle) -> Self {
+        Image::new().rect([r.x, r.y, r.w, r.h])
     }
 }
@@ -1,7 +1,9 @@
-use crate::piece::{Piece, Side};
 use crate::ChessboardController;
-use crate::{BOARD_BORDER_SIZE, BOARD_SIZE};
-use graphics;
+use crate::BOARD_BORDER_SIZE;
+use chess::attacks::square_pos;
+use chess::bitboard::bits;
+use chess::piece::{Piece, Side};
+use chess::BOARD_SIZE;
 use graphics::types::Color;
 use opengl_graphics::{GlGraphics, Texture, TextureSettings};
 use std::path::Path;
@@ -23,6 +25,7 @@ pub struct ChesspieceTextures {
 }
 
 impl ChesspieceTextures {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_paths(
         light_king_path: &str,
         light_queen_path: &str,
@@ -122,6 +125,16 @@ pub struct ChessboardViewSettings {
     pub board_edge_color: Color,
     /// Edge radius around the whole board.
     pub board_edge_size: f64,
+    /// Color of the outline around pieces that are hanging.
+    pub hanging_piece_color: Color,
+    /// Thickness of the outline around pieces that are hanging.
+    pub hanging_piece_edge_size: f64,
+    /// Color laid over the squares the other side attacks, when attacks are shown.
+    pub attacked_square_color: Color,
+    /// Color of the outline around pinned pieces, when attacks are shown.
+    pub pinned_piece_color: Color,
+    /// Color of the outline around pieces giving check, when attacks are shown.
+    pub checker_color: Color,
     /// Struct storing the textures for the chesspieces
     pub textures: ChesspieceTextures,
 }
@@ -147,6 +160,11 @@ impl ChessboardViewSettings {
             dark_square_color: rgb_to_decimal(125, 171, 219),
             board_edge_color: [0.0, 0.0, 0.2, 1.0],
             board_edge_size: BOARD_BORDER_SIZE,
+            hanging_piece_color: [0.8, 0.1, 0.1, 1.0],
+            hanging_piece_edge_size: 2.0,
+            attacked_square_color: [0.9, 0.3, 0.1, 0.25],
+            pinned_piece_color: [0.9, 0.8, 0.1, 1.0],
+            checker_color: [0.9, 0.4, 0.0, 1.0],
             textures,
         }
     }
@@ -213,6 +231,36 @@ impl ChessboardView {
             g,
         );
 
+        if controller.show_attacks {
+            for sq in bits(controller.attacked) {
+                let rect: [f64; 4] = controller.square_rect(square_pos(sq)).into();
+                Rectangle::new(settings.attacked_square_color).draw(
+                    rect,
+                    &c.draw_state,
+                    c.transform,
+                    g,
+                );
+            }
+            let outlines = [
+                (controller.pinned, settings.pinned_piece_color),
+                (controller.checkers, settings.checker_color),
+            ];
+            for (squares, color) in outlines.iter() {
+                for sq in bits(*squares) {
+                    let rect: [f64; 4] = controller.square_rect(square_pos(sq)).into();
+                    Rectangle::new_border(*color, settings.hanging_piece_edge_size)
+                        .draw(rect, &c.draw_state, c.transform, g);
+                }
+            }
+        }
+
+        // warn about pieces the player to move is about to lose
+        for &pos in &controller.hanging {
+            let rect: [f64; 4] = controller.square_rect(pos).into();
+            Rectangle::new_border(settings.hanging_piece_color, settings.hanging_piece_edge_size)
+                .draw(rect, &c.draw_state, c.transform, g);
+        }
+
         //for (_, piece) in controller.chessboard.get_pieces() {
         for piece_rect in &controller.piece_rects {
             let img: Image = (&piece_rect.rect).into();
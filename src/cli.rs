@@ -0,0 +1,62 @@
+use crate::{eval_params, logger, nnue};
+
+// What the command line programs have in common.
+
+/// Takes the options every program accepts out of `args`, and acts on them:
+/// - `--log-level <level>` and `--log-file <file>` decide which diagnostics get logged, and where
+///   to (by default, `info` and up, to stderr)
+/// - `--params <file>` loads the evaluation parameters from a file
+/// - `--net <file>` loads an NNUE network, which is then used instead of the classical evaluation
+pub fn take_common_options(args: &mut Vec<String>) {
+    let mut log_level = String::from("info");
+    if let Some(i) = args.iter().position(|arg| arg == "--log-level") {
+        log_level = args.get(i + 1).unwrap_or_else(|| fail("--log-level needs a level")).clone();
+        args.drain(i..=i + 1);
+    }
+    let mut log_file = None;
+    if let Some(i) = args.iter().position(|arg| arg == "--log-file") {
+        log_file = Some(args.get(i + 1).unwrap_or_else(|| fail("--log-file needs a file")).clone());
+        args.drain(i..=i + 1);
+    }
+    if let Err(e) = logger::init(&log_level, log_file.as_deref()) {
+        fail(format!("Couldn't set up logging: {}", e));
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--params") {
+        let path = args.get(i + 1).unwrap_or_else(|| fail("--params needs a file")).clone();
+        match eval_params::EvalParams::load(&path) {
+            Ok(params) => {
+                eval_params::set_params(params);
+                log::info!("Loaded evaluation parameters from {}", path);
+            }
+            Err(e) => fail(format!("Couldn't load evaluation parameters: {}", e)),
+        }
+        args.drain(i..=i + 1);
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--net") {
+        let path = args.get(i + 1).unwrap_or_else(|| fail("--net needs a file")).clone();
+        match nnue::Network::load(&path) {
+            Ok(network) => {
+                nnue::set_network(network);
+                log::info!("Loaded the network from {}", path);
+            }
+            Err(e) => fail(format!("Couldn't load the network: {}", e)),
+        }
+        args.drain(i..=i + 1);
+    }
+}
+
+/// Prints `message` and exits, for mistakes on the command line and files that can't be used,
+/// which aren't bugs, so they shouldn't look like a crash
+pub fn fail(message: impl AsRef<str>) -> ! {
+    eprintln!("{}", message.as_ref());
+    std::process::exit(1);
+}
+
+/// Removes `flag` from `args`, and returns whether it was there
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
@@ -0,0 +1,580 @@
+use crate::bitboard::{
+    adjacent_files, bits, file_mask, pawn_attacks, piece_attacks, rank_mask, Bitboards,
+    KING_ATTACKS,
+};
+use crate::chessboard::Chessboard;
+use crate::eval_params::EvalParams;
+use crate::nnue::{self, Nnue};
+use crate::piece::{kind_idx, side_idx, Side, KING, KNIGHT, PAWN, QUEEN};
+use std::fmt;
+use std::ops::{AddAssign, Neg, Sub};
+
+// Every score here is in centipawns, from Light's point of view.
+
+/// The game phase with all the pieces on the board (using the default `phase_weights`), which is
+/// pure middlegame. As pieces come off, it drops towards 0, which is pure endgame.
+const MAX_PHASE: i32 = 24;
+
+/// Number of entries in the pawn structure cache
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+/// A middlegame and an endgame score, which get blended together by the game phase
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tapered {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Tapered {
+    pub const fn new(mg: i32, eg: i32) -> Tapered {
+        Tapered { mg, eg }
+    }
+
+    /// Interpolates between the middlegame and endgame scores. Promotions (or unusual phase
+    /// weights) can push the phase outside of 0 to `MAX_PHASE`, so it's clamped.
+    pub fn taper(self, phase: i32) -> i32 {
+        let phase = phase.clamp(0, MAX_PHASE);
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl AddAssign for Tapered {
+    fn add_assign(&mut self, other: Tapered) {
+        self.mg += other.mg;
+        self.eg += other.eg;
+    }
+}
+
+impl Sub for Tapered {
+    type Output = Tapered;
+    fn sub(self, other: Tapered) -> Tapered {
+        Tapered::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Tapered {
+    type Output = Tapered;
+    fn neg(self) -> Tapered {
+        Tapered::new(-self.mg, -self.eg)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    /// Everything but the passed pawns, for each side
+    structure: [Tapered; 2],
+    /// The passed pawns of each side. Whether their path is free depends on the other pieces, so
+    /// that part can't be cached.
+    passed: [u64; 2],
+}
+
+/// Caches the pawn structure evaluation by `Chessboard::pawn_hash`. Pawns hardly ever move compared
+/// to the other pieces, so almost every lookup is a hit. Each search thread owns its own.
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        // An empty entry has key 0 and scores nothing, which happens to be correct for a board
+        // with no pawns on it.
+        PawnTable {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+
+    fn probe(&mut self, key: u64, pawns: [u64; 2], params: &EvalParams) -> PawnEntry {
+        let entry = &mut self.entries[(key % PAWN_TABLE_SIZE as u64) as usize];
+        if entry.key != key {
+            *entry = PawnEntry::new(key, pawns, params);
+        }
+        *entry
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> PawnTable {
+        PawnTable::new()
+    }
+}
+
+impl PawnEntry {
+    fn new(key: u64, pawns: [u64; 2], params: &EvalParams) -> PawnEntry {
+        let (light, light_passed) = pawn_structure(Side::Light, pawns, params);
+        let (dark, dark_passed) = pawn_structure(Side::Dark, pawns, params);
+        PawnEntry {
+            key,
+            structure: [light, dark],
+            passed: [light_passed, dark_passed],
+        }
+    }
+}
+
+/// The parts the evaluation is made of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Term {
+    Material,
+    PieceSquares,
+    PawnStructure,
+    PassedPawns,
+    Mobility,
+    KingSafety,
+}
+
+impl Term {
+    pub const ALL: [Term; 6] = [
+        Term::Material,
+        Term::PieceSquares,
+        Term::PawnStructure,
+        Term::PassedPawns,
+        Term::Mobility,
+        Term::KingSafety,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Term::Material => "Material",
+            Term::PieceSquares => "Piece squares",
+            Term::PawnStructure => "Pawn structure",
+            Term::PassedPawns => "Passed pawns",
+            Term::Mobility => "Mobility",
+            Term::KingSafety => "King safety",
+        }
+    }
+}
+
+/// Every term of the evaluation, for each side, before it's blended by the game phase. Printing
+/// it gives a table of the whole thing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Explanation {
+    /// Indexed by `Term`, then `side_idx`. Each side's scores are from its own point of view.
+    terms: [[Tapered; 2]; Term::ALL.len()],
+    /// Between 0 (endgame) and `MAX_PHASE` (middlegame)
+    pub phase: i32,
+}
+
+impl Explanation {
+    /// What `term` scores for `side`, from its own point of view
+    pub fn term(&self, term: Term, side: Side) -> Tapered {
+        self.terms[term as usize][side_idx(side)]
+    }
+
+    /// What `term` is worth to Light, after taking away what it's worth to Dark
+    pub fn net(&self, term: Term) -> Tapered {
+        self.term(term, Side::Light) - self.term(term, Side::Dark)
+    }
+
+    /// The final evaluation, from Light's point of view
+    pub fn score(&self) -> i32 {
+        let mut total = Tapered::default();
+        for &term in Term::ALL.iter() {
+            total += self.net(term);
+        }
+        total.taper(self.phase)
+    }
+
+    fn add(&mut self, term: Term, side: Side, score: Tapered) {
+        self.terms[term as usize][side_idx(side)] += score;
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<15}| {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6} {:>6}",
+            "Term", "White", "", "Black", "", "Total", "", ""
+        )?;
+        writeln!(
+            f,
+            "{:<15}| {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6} {:>6}",
+            "", "MG", "EG", "MG", "EG", "MG", "EG", "Phased"
+        )?;
+        writeln!(f, "{:-<15}+{:-<15}+{:-<15}+{:-<22}", "", "", "", "")?;
+        for &term in Term::ALL.iter() {
+            let light = self.term(term, Side::Light);
+            let dark = self.term(term, Side::Dark);
+            let net = self.net(term);
+            writeln!(
+                f,
+                "{:<15}| {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6} {:>6}",
+                term.name(),
+                light.mg,
+                light.eg,
+                dark.mg,
+                dark.eg,
+                net.mg,
+                net.eg,
+                net.taper(self.phase)
+            )?;
+        }
+        writeln!(f, "{:-<15}+{:-<15}+{:-<15}+{:-<22}", "", "", "", "")?;
+        write!(
+            f,
+            "Phase {}/{}, score {} (from White's point of view)",
+            self.phase.clamp(0, MAX_PHASE),
+            MAX_PHASE,
+            self.score()
+        )
+    }
+}
+
+/// Something the search can evaluate positions with. Every search thread gets its own, so they can
+/// keep caches.
+pub trait Evaluator {
+    /// The evaluation of `chessboard` in centipawns, from White's point of view
+    fn evaluate(&mut self, chessboard: &Chessboard) -> i32;
+}
+
+/// The hand-written evaluation, `heuristic_score`
+pub struct Classical<'a> {
+    params: &'a EvalParams,
+    pawn_table: PawnTable,
+}
+
+impl<'a> Classical<'a> {
+    pub fn new(params: &'a EvalParams) -> Classical<'a> {
+        Classical {
+            params,
+            pawn_table: PawnTable::new(),
+        }
+    }
+}
+
+impl Evaluator for Classical<'_> {
+    fn evaluate(&mut self, chessboard: &Chessboard) -> i32 {
+        heuristic_score(chessboard, self.params, &mut self.pawn_table)
+    }
+}
+
+/// The network, if one was loaded and `use_nnue` is set, or the classical evaluation otherwise
+pub fn evaluator(params: &EvalParams, use_nnue: bool) -> Box<dyn Evaluator + '_> {
+    match nnue::network() {
+        Some(network) if use_nnue => Box::new(Nnue::new(network)),
+        _ => Box::new(Classical::new(params)),
+    }
+}
+
+/// Material, piece square tables, pawn structure, mobility and king safety, blended between the
+/// middlegame and endgame values by how much material is left. Checkmate and stalemate are left to
+/// the search, which knows how far away they are.
+pub fn heuristic_score(
+    chessboard: &Chessboard,
+    params: &EvalParams,
+    pawn_table: &mut PawnTable,
+) -> i32 {
+    evaluate(chessboard, params, Some(pawn_table)).score()
+}
+
+/// Breaks the evaluation of `chessboard` down into all its terms, to see what's behind a score.
+pub fn evaluate_explained(chessboard: &Chessboard, params: &EvalParams) -> Explanation {
+    evaluate(chessboard, params, None)
+}
+
+fn evaluate(
+    chessboard: &Chessboard,
+    params: &EvalParams,
+    pawn_table: Option<&mut PawnTable>,
+) -> Explanation {
+    let mut explanation = Explanation::default();
+    let bitboards = chessboard.bitboards();
+
+    for piece in chessboard.pieces().values() {
+        let kind = kind_idx(piece);
+        let data = piece.data();
+        let sq = pst_idx(data.position, data.side);
+        explanation.add(
+            Term::Material,
+            data.side,
+            Tapered::new(params.mg_values[kind], params.eg_values[kind]),
+        );
+        explanation.add(
+            Term::PieceSquares,
+            data.side,
+            Tapered::new(params.mg_pst[kind][sq], params.eg_pst[kind][sq]),
+        );
+        explanation.phase += params.phase_weights[kind];
+    }
+
+    let pawns = [bitboards.pieces[0][PAWN], bitboards.pieces[1][PAWN]];
+    let entry = match pawn_table {
+        Some(pawn_table) => pawn_table.probe(chessboard.pawn_hash(), pawns, params),
+        None => PawnEntry::new(chessboard.pawn_hash(), pawns, params),
+    };
+
+    for &side in [Side::Light, Side::Dark].iter() {
+        let passed = entry.passed[side_idx(side)];
+        explanation.add(Term::PawnStructure, side, entry.structure[side_idx(side)]);
+        let passed = passed_pawns(side, passed, bitboards.occupied, params);
+        explanation.add(Term::PassedPawns, side, passed);
+        explanation.add(Term::Mobility, side, mobility(side, bitboards, params));
+        explanation.add(Term::KingSafety, side, king_safety(side, bitboards, params));
+    }
+
+    explanation
+}
+
+fn mobility(side: Side, bitboards: &Bitboards, params: &EvalParams) -> Tapered {
+    let (us, them) = (side_idx(side), side_idx(side.other()));
+    let area = !bitboards.occupied_by[us] & !pawn_attacks(bitboards.pieces[them][PAWN], side.other());
+
+    let mut score = Tapered::default();
+    // pawns and kings don't count
+    for kind in KNIGHT..=QUEEN {
+        for sq in bits(bitboards.pieces[us][kind]) {
+            let attacks = piece_attacks(kind, sq, bitboards.occupied) & area;
+            let squares = attacks.count_ones() as i32 - params.mobility_base[kind];
+            score += Tapered::new(
+                params.mobility_mg[kind] * squares,
+                params.mobility_eg[kind] * squares,
+            );
+        }
+    }
+    score
+}
+
+/// How safe `side`'s king is, from the pawns sheltering it, open files next to it, and the enemy
+/// pieces attacking the squares around it. This only matters in the middlegame. In the endgame,
+/// the king should be out in the middle of the board, which the piece square tables take care of.
+fn king_safety(side: Side, bitboards: &Bitboards, params: &EvalParams) -> Tapered {
+    let (us, them) = (side_idx(side), side_idx(side.other()));
+    let king = bitboards.pieces[us][KING];
+    if king == 0 {
+        return Tapered::default();
+    }
+    let ksq = king.trailing_zeros() as usize;
+    let (kfile, krank) = (ksq % 8, ksq / 8);
+    let ours = bitboards.pieces[us][PAWN];
+    let theirs = bitboards.pieces[them][PAWN];
+
+    let mut score = 0;
+    for file in kfile.saturating_sub(1)..=(kfile + 1).min(7) {
+        let file = file_mask(file);
+        let shield_ranks = forward_rank(krank, side)
+            .into_iter()
+            .chain(forward_rank(krank, side).and_then(|r| forward_rank(r, side)));
+        for (rank, bonus) in shield_ranks.zip(&params.pawn_shield) {
+            if ours & file & rank_mask(rank) != 0 {
+                score += bonus;
+                break;
+            }
+        }
+
+        if ours & file == 0 {
+            score += if theirs & file == 0 {
+                params.open_file_near_king
+            } else {
+                params.semi_open_file_near_king
+            };
+        }
+    }
+
+    let zone = KING_ATTACKS[ksq] | king;
+    let mut attackers = 0;
+    let mut weight = 0;
+    // only knights, bishops, rooks and queens have a weight
+    for (kind, &hit_weight) in params.king_attack_weights.iter().enumerate().filter(|(_, w)| **w > 0) {
+        for sq in bits(bitboards.pieces[them][kind]) {
+            let hits = (piece_attacks(kind, sq, bitboards.occupied) & zone).count_ones() as i32;
+            if hits > 0 {
+                attackers += 1;
+                weight += hit_weight * hits;
+            }
+        }
+    }
+    score -= weight * params.king_attackers_scale[attackers.min(7)] / 10;
+
+    Tapered::new(score, 0)
+}
+
+/// Scores `side`'s doubled, isolated, backward and connected pawns, and finds its passed pawns.
+fn pawn_structure(side: Side, pawns: [u64; 2], params: &EvalParams) -> (Tapered, u64) {
+    let ours = pawns[side_idx(side)];
+    let theirs = pawns[side_idx(side.other())];
+    let mut score = Tapered::default();
+    let mut passed = 0;
+
+    for sq in bits(ours) {
+        let (file, rank) = (sq % 8, sq / 8);
+        let rel_rank = relative_rank(rank, side);
+        let ahead = forward_ranks(rank, side);
+        let neighbours = adjacent_files(file);
+
+        let doubled = ours & file_mask(file) & ahead != 0;
+        let isolated = ours & neighbours == 0;
+        if doubled {
+            score += params.doubled;
+        }
+        if isolated {
+            score += params.isolated;
+        }
+
+        // a pawn further back on the same file isn't passed, the one in front of it is
+        if !doubled && theirs & (file_mask(file) | neighbours) & ahead == 0 {
+            passed |= 1 << sq;
+        }
+
+        let phalanx = ours & neighbours & rank_mask(rank) != 0;
+        let supported = ours & neighbours & pawn_attacks_from(side, rank) != 0;
+        if phalanx || supported {
+            score += Tapered::new(params.connected_mg[rel_rank], params.connected_eg[rel_rank]);
+        }
+
+        // every pawn that could defend it is further up the board, and its stop square is
+        // attacked by an enemy pawn
+        if !isolated && ours & neighbours & !ahead == 0 {
+            let stop_rank = forward_rank(rank, side);
+            let attackers = stop_rank.map_or(0, |stop| pawn_attacks_from(side.other(), stop));
+            if theirs & neighbours & attackers != 0 {
+                score += params.backward;
+            }
+        }
+    }
+
+    (score, passed)
+}
+
+/// Scores `side`'s `passed` pawns by how far they've advanced, with an extra bonus in the endgame
+/// for the ones with nothing in the way of promoting.
+fn passed_pawns(side: Side, passed: u64, occupied: u64, params: &EvalParams) -> Tapered {
+    let mut score = Tapered::default();
+    for sq in bits(passed) {
+        let (file, rank) = (sq % 8, sq / 8);
+        let rel_rank = relative_rank(rank, side);
+        score += Tapered::new(params.passed_mg[rel_rank], params.passed_eg[rel_rank]);
+
+        if occupied & file_mask(file) & forward_ranks(rank, side) == 0 {
+            score += Tapered::new(0, params.passed_free_eg[rel_rank]);
+        }
+    }
+    score
+}
+
+/// Every rank in front of `rank`, from `side`'s point of view
+fn forward_ranks(rank: usize, side: Side) -> u64 {
+    match side {
+        Side::Light if rank == 7 => 0,
+        Side::Light => !0 << ((rank + 1) * 8),
+        Side::Dark => (1 << (rank * 8)) - 1,
+    }
+}
+
+/// The rank one step in front of `rank` for `side`, if there is one
+fn forward_rank(rank: usize, side: Side) -> Option<usize> {
+    match side {
+        Side::Light if rank < 7 => Some(rank + 1),
+        Side::Dark if rank > 0 => Some(rank - 1),
+        _ => None,
+    }
+}
+
+/// The rank that pawns of `side` would have to stand on to attack a square on `rank`
+fn pawn_attacks_from(side: Side, rank: usize) -> u64 {
+    forward_rank(rank, side.other()).map_or(0, rank_mask)
+}
+
+/// The rank counting from `side`'s own back rank
+#[inline(always)]
+fn relative_rank(rank: usize, side: Side) -> usize {
+    match side {
+        Side::Light => rank,
+        Side::Dark => 7 - rank,
+    }
+}
+
+/// Where `pos` is in the piece square tables for `side`
+#[inline(always)]
+fn pst_idx(pos: [u8; 2], side: Side) -> usize {
+    let [file, rank] = pos;
+    let row = match side {
+        Side::Light => 7 - rank,
+        Side::Dark => rank,
+    };
+    (row * 8 + file) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chessboard::STARTPOS;
+    use crate::eval_params::EvalParams;
+
+    const POSITIONS: [&str; 6] = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        // passed pawns on both sides, and an exposed king
+        "6k1/P4p2/6p1/8/1p6/8/5PPP/6K1 b - - 0 1",
+        "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+    ];
+
+    /// The same position with the board turned upside down and the colours swapped
+    fn flip(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let swap_case = |s: &str| -> String {
+            s.chars()
+                .map(|c| {
+                    if c.is_ascii_uppercase() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c.to_ascii_uppercase()
+                    }
+                })
+                .collect()
+        };
+        let board: Vec<String> = fields[0].split('/').rev().map(swap_case).collect();
+        let turn = if fields[1] == "w" { "b" } else { "w" };
+        let ep = match fields[3].as_bytes() {
+            [file, rank] => format!("{}{}", *file as char, (b'1' + b'8' - rank) as char),
+            _ => String::from("-"),
+        };
+        format!("{} {} {} {} 0 1", board.join("/"), turn, swap_case(fields[2]), ep)
+    }
+
+    #[test]
+    fn explanation_adds_up_to_the_score() {
+        let params = EvalParams::default();
+        let mut pawn_table = PawnTable::new();
+        for fen in POSITIONS.iter() {
+            let chessboard = Chessboard::from_fen(String::from(*fen));
+            let explanation = evaluate_explained(&chessboard, &params);
+            // twice, so the second time the pawn structure comes out of the cache
+            for _ in 0..2 {
+                let score = heuristic_score(&chessboard, &params, &mut pawn_table);
+                assert_eq!(explanation.score(), score, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn terms_are_symmetric() {
+        let params = EvalParams::default();
+        for fen in POSITIONS.iter() {
+            let chessboard = Chessboard::from_fen(String::from(*fen));
+            let explanation = evaluate_explained(&chessboard, &params);
+            let flipped = evaluate_explained(&Chessboard::from_fen(flip(fen)), &params);
+            assert_eq!(explanation.phase, flipped.phase, "{}", fen);
+            for &term in Term::ALL.iter() {
+                let light = explanation.term(term, Side::Light);
+                let dark = explanation.term(term, Side::Dark);
+                assert_eq!(light, flipped.term(term, Side::Dark), "{} in {}", term.name(), fen);
+                assert_eq!(dark, flipped.term(term, Side::Light), "{} in {}", term.name(), fen);
+                let net = explanation.net(term);
+                assert_eq!(net, -flipped.net(term), "{} in {}", term.name(), fen);
+            }
+            assert_eq!(explanation.score(), -flipped.score(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn start_position_is_level() {
+        let chessboard = Chessboard::from_fen(String::from(STARTPOS));
+        let explanation = evaluate_explained(&chessboard, &EvalParams::default());
+        for &term in Term::ALL.iter() {
+            assert_eq!(explanation.net(term), Tapered::default(), "{}", term.name());
+        }
+        assert_eq!(explanation.score(), 0);
+    }
+}
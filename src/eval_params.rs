@@ -0,0 +1,434 @@
+use crate::eval::Tapered;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+
+// These are the default parameters, which are compiled in. Pieces are indexed in the order pawn,
+// knight, bishop, rook, queen, king.
+
+// Penalties for each doubled (a pawn with another of ours in front of it on the same file),
+// isolated (no pawns of ours on the files next to it), and backward (every pawn that could defend
+// it has already gone past it, and it can't advance without being taken by a pawn) pawn.
+const DOUBLED: Tapered = Tapered::new(-10, -20);
+const ISOLATED: Tapered = Tapered::new(-10, -15);
+const BACKWARD: Tapered = Tapered::new(-8, -12);
+
+// The rest are indexed by rank, counting from the side's own back rank.
+
+/// For pawns defended by another pawn, or standing next to one
+const CONNECTED_MG: [i32; 8] = [0, 5, 7, 10, 20, 35, 60, 0];
+const CONNECTED_EG: [i32; 8] = [0, 3, 5, 8, 15, 25, 40, 0];
+
+/// For pawns with no enemy pawns in front of them, on their own file or the ones next to it
+const PASSED_MG: [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+const PASSED_EG: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+
+/// Added on to `PASSED_EG` when nothing at all stands between a passed pawn and promotion
+const PASSED_FREE_EG: [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+
+/// How much each kind of piece counts towards the game phase (pawns and kings don't). These add up
+/// to 24 with all the pieces on the board.
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+
+// Mobility counts the squares each piece attacks, other than ones occupied by our own pieces or
+// attacked by enemy pawns. A piece with its baseline number of such squares scores nothing, and
+// every square above or below that is worth its mobility weight.
+const MOBILITY_BASE: [i32; 6] = [0, 4, 7, 7, 14, 0];
+const MOBILITY_MG: [i32; 6] = [0, 4, 5, 2, 1, 0];
+const MOBILITY_EG: [i32; 6] = [0, 4, 5, 4, 2, 0];
+
+/// For each file next to the king, a pawn of ours one or two steps in front of it
+const PAWN_SHIELD: [i32; 2] = [15, 8];
+/// For each file next to the king without any of our pawns, if there's an enemy pawn on it or not
+const SEMI_OPEN_FILE_NEAR_KING: i32 = -15;
+const OPEN_FILE_NEAR_KING: i32 = -25;
+
+/// How dangerous each kind of piece attacking a square next to the enemy king is
+const KING_ATTACK_WEIGHTS: [i32; 6] = [0, 2, 2, 3, 5, 0];
+/// One piece near the king isn't much of a threat on its own, so the attack weight is scaled by
+/// this percentage, indexed by the number of attacking pieces.
+const KING_ATTACKERS_SCALE: [i32; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+
+// Material
+const MG_VALUES: [i32; 6] = [82, 337, 365, 477, 1025, 0];
+const EG_VALUES: [i32; 6] = [94, 281, 297, 512, 936, 0];
+
+// Piece square tables, from Light's point of view, with a8 first and h1 last (so they look like
+// the board does from Light's side). Dark's pieces use them flipped vertically.
+#[rustfmt::skip]
+const MG_PST: [[i32; 64]; 6] = [
+    // Pawn
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         98, 134,  61,  95,  68, 126,  34, -11,
+         -6,   7,  26,  31,  65,  56,  25, -20,
+        -14,  13,   6,  21,  23,  12,  17, -23,
+        -27,  -2,  -5,  12,  17,   6,  10, -25,
+        -26,  -4,  -4, -10,   3,   3,  33, -12,
+        -35,  -1, -20, -23, -15,  24,  38, -22,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    // Knight
+    [
+        -167, -89, -34, -49,  61, -97, -15, -107,
+         -73, -41,  72,  36,  23,  62,   7,  -17,
+         -47,  60,  37,  65,  84, 129,  73,   44,
+          -9,  17,  19,  53,  37,  69,  18,   22,
+         -13,   4,  16,  13,  28,  19,  21,   -8,
+         -23,  -9,  12,  10,  19,  17,  25,  -16,
+         -29, -53, -12,  -3,  -1,  18, -14,  -19,
+        -105, -21, -58, -33, -17, -28, -19,  -23,
+    ],
+    // Bishop
+    [
+        -29,   4, -82, -37, -25, -42,   7,  -8,
+        -26,  16, -18, -13,  30,  59,  18, -47,
+        -16,  37,  43,  40,  35,  50,  37,  -2,
+         -4,   5,  19,  50,  37,  37,   7,  -2,
+         -6,  13,  13,  26,  34,  12,  10,   4,
+          0,  15,  15,  15,  14,  27,  18,  10,
+          4,  15,  16,   0,   7,  21,  33,   1,
+        -33,  -3, -14, -21, -13, -12, -39, -21,
+    ],
+    // Rook
+    [
+         32,  42,  32,  51,  63,   9,  31,  43,
+         27,  32,  58,  62,  80,  67,  26,  44,
+         -5,  19,  26,  36,  17,  45,  61,  16,
+        -24, -11,   7,  26,  24,  35,  -8, -20,
+        -36, -26, -12,  -1,   9,  -7,   6, -23,
+        -45, -25, -16, -17,   3,   0,  -5, -33,
+        -44, -16, -20,  -9,  -1,  11,  -6, -71,
+        -19, -13,   1,  17,  16,   7, -37, -26,
+    ],
+    // Queen
+    [
+        -28,   0,  29,  12,  59,  44,  43,  45,
+        -24, -39,  -5,   1, -16,  57,  28,  54,
+        -13, -17,   7,   8,  29,  56,  47,  57,
+        -27, -27, -16, -16,  -1,  17,  -2,   1,
+         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+        -14,   2, -11,  -2,  -5,   2,  14,   5,
+        -35,  -8,  11,   2,   8,  15,  -3,   1,
+         -1, -18,  -9,  10, -15, -25, -31, -50,
+    ],
+    // King
+    [
+        -65,  23,  16, -15, -56, -34,   2,  13,
+         29,  -1, -20,  -7,  -8,  -4, -38, -29,
+         -9,  24,   2, -16, -20,   6,  22, -22,
+        -17, -20, -12, -27, -30, -25, -14, -36,
+        -49,  -1, -27, -39, -46, -44, -33, -51,
+        -14, -14, -22, -46, -44, -30, -15, -27,
+          1,   7,  -8, -64, -43, -16,   9,   8,
+        -15,  36,  12, -54,   8, -28,  24,  14,
+    ],
+];
+
+#[rustfmt::skip]
+const EG_PST: [[i32; 64]; 6] = [
+    // Pawn
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+        178, 173, 158, 134, 147, 132, 165, 187,
+         94, 100,  85,  67,  56,  53,  82,  84,
+         32,  24,  13,   5,  -2,   4,  17,  17,
+         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+          4,   7,  -6,   1,   0,  -5,  -1,  -8,
+         13,   8,   8,  10,  13,   0,   2,  -7,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    // Knight
+    [
+        -58, -38, -13, -28, -31, -27, -63, -99,
+        -25,  -8, -25,  -2,  -9, -25, -24, -52,
+        -24, -20,  10,   9,  -1,  -9, -19, -41,
+        -17,   3,  22,  22,  22,  11,   8, -18,
+        -18,  -6,  16,  25,  16,  17,   4, -18,
+        -23,  -3,  -1,  15,  10,  -3, -20, -22,
+        -42, -20, -10,  -5,  -2, -20, -23, -44,
+        -29, -51, -23, -15, -22, -18, -50, -64,
+    ],
+    // Bishop
+    [
+        -14, -21, -11,  -8,  -7,  -9, -17, -24,
+         -8,  -4,   7, -12,  -3, -13,  -4, -14,
+          2,  -8,   0,  -1,  -2,   6,   0,   4,
+         -3,   9,  12,   9,  14,  10,   3,   2,
+         -6,   3,  13,  19,   7,  10,  -3,  -9,
+        -12,  -3,   8,  10,  13,   3,  -7, -15,
+        -14, -18,  -7,  -1,   4,  -9, -15, -27,
+        -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+    ],
+    // Rook
+    [
+         13,  10,  18,  15,  12,  12,   8,   5,
+         11,  13,  13,  11,  -3,   3,   8,   3,
+          7,   7,   7,   5,   4,  -3,  -5,  -3,
+          4,   3,  13,   1,   2,   1,  -1,   2,
+          3,   5,   8,   4,  -5,  -6,  -8, -11,
+         -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+         -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+         -9,   2,   3,  -1,  -5, -13,   4, -20,
+    ],
+    // Queen
+    [
+         -9,  22,  22,  27,  27,  19,  10,  20,
+        -17,  20,  32,  41,  58,  25,  30,   0,
+        -20,   6,   9,  49,  47,  35,  19,   9,
+          3,  22,  24,  45,  57,  40,  57,  36,
+        -18,  28,  19,  47,  31,  34,  39,  23,
+        -16, -27,  15,   6,   9,  17,  10,   5,
+        -22, -23, -30, -16, -16, -23, -36, -32,
+        -33, -28, -22, -43,  -5, -32, -20, -41,
+    ],
+    // King
+    [
+        -74, -35, -18, -18, -11,  15,   4, -17,
+        -12,  17,  14,  17,  17,  38,  23,  11,
+         10,  17,  23,  15,  20,  45,  44,  13,
+         -8,  22,  24,  27,  26,  33,  26,   3,
+        -18,  -4,  21,  24,  27,  23,   9, -11,
+        -19,  -3,  11,  21,  23,  16,   7,  -9,
+        -27, -11,   4,  13,  14,   4,  -5, -17,
+        -53, -34, -21, -11, -28, -14, -24, -43,
+    ],
+];
+
+const KIND_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+/// Every weight the evaluation uses. The defaults are compiled in, but a different set can be
+/// loaded from a file at startup, so they can be experimented with (or tuned) without recompiling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalParams {
+    pub mg_values: [i32; 6],
+    pub eg_values: [i32; 6],
+    pub mg_pst: [[i32; 64]; 6],
+    pub eg_pst: [[i32; 64]; 6],
+    pub doubled: Tapered,
+    pub isolated: Tapered,
+    pub backward: Tapered,
+    pub connected_mg: [i32; 8],
+    pub connected_eg: [i32; 8],
+    pub passed_mg: [i32; 8],
+    pub passed_eg: [i32; 8],
+    pub passed_free_eg: [i32; 8],
+    pub phase_weights: [i32; 6],
+    pub mobility_base: [i32; 6],
+    pub mobility_mg: [i32; 6],
+    pub mobility_eg: [i32; 6],
+    pub pawn_shield: [i32; 2],
+    pub semi_open_file_near_king: i32,
+    pub open_file_near_king: i32,
+    pub king_attack_weights: [i32; 6],
+    pub king_attackers_scale: [i32; 8],
+}
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        EvalParams {
+            mg_values: MG_VALUES,
+            eg_values: EG_VALUES,
+            mg_pst: MG_PST,
+            eg_pst: EG_PST,
+            doubled: DOUBLED,
+            isolated: ISOLATED,
+            backward: BACKWARD,
+            connected_mg: CONNECTED_MG,
+            connected_eg: CONNECTED_EG,
+            passed_mg: PASSED_MG,
+            passed_eg: PASSED_EG,
+            passed_free_eg: PASSED_FREE_EG,
+            phase_weights: PHASE_WEIGHTS,
+            mobility_base: MOBILITY_BASE,
+            mobility_mg: MOBILITY_MG,
+            mobility_eg: MOBILITY_EG,
+            pawn_shield: PAWN_SHIELD,
+            semi_open_file_near_king: SEMI_OPEN_FILE_NEAR_KING,
+            open_file_near_king: OPEN_FILE_NEAR_KING,
+            king_attack_weights: KING_ATTACK_WEIGHTS,
+            king_attackers_scale: KING_ATTACKERS_SCALE,
+        }
+    }
+}
+
+impl EvalParams {
+    /// Calls `f` with the name and values of every parameter, in the order they're written out.
+    pub fn visit_mut(&mut self, f: &mut dyn FnMut(&str, &mut [i32])) {
+        f("mg_values", &mut self.mg_values);
+        f("eg_values", &mut self.eg_values);
+        for (kind, name) in KIND_NAMES.iter().enumerate() {
+            f(&format!("mg_pst_{}", name), &mut self.mg_pst[kind]);
+        }
+        for (kind, name) in KIND_NAMES.iter().enumerate() {
+            f(&format!("eg_pst_{}", name), &mut self.eg_pst[kind]);
+        }
+        visit_tapered(f, "doubled", &mut self.doubled);
+        visit_tapered(f, "isolated", &mut self.isolated);
+        visit_tapered(f, "backward", &mut self.backward);
+        f("connected_mg", &mut self.connected_mg);
+        f("connected_eg", &mut self.connected_eg);
+        f("passed_mg", &mut self.passed_mg);
+        f("passed_eg", &mut self.passed_eg);
+        f("passed_free_eg", &mut self.passed_free_eg);
+        f("phase_weights", &mut self.phase_weights);
+        f("mobility_base", &mut self.mobility_base);
+        f("mobility_mg", &mut self.mobility_mg);
+        f("mobility_eg", &mut self.mobility_eg);
+        f("pawn_shield", &mut self.pawn_shield);
+        f(
+            "semi_open_file_near_king",
+            std::slice::from_mut(&mut self.semi_open_file_near_king),
+        );
+        f("open_file_near_king", std::slice::from_mut(&mut self.open_file_near_king));
+        f("king_attack_weights", &mut self.king_attack_weights);
+        f("king_attackers_scale", &mut self.king_attackers_scale);
+    }
+
+    /// Reads parameters in the format they're displayed in: each parameter's name followed by its
+    /// values, which may go on for several lines. Anything after a `#` is a comment. Parameters
+    /// that aren't mentioned keep their default values.
+    pub fn parse(text: &str) -> Result<EvalParams, String> {
+        let mut values: HashMap<String, Vec<i32>> = HashMap::new();
+        let mut current: Option<String> = None;
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            for token in line.split_whitespace() {
+                if let Ok(value) = token.parse::<i32>() {
+                    match &current {
+                        Some(name) => values.get_mut(name).unwrap().push(value),
+                        None => return Err(format!("line {}: {} has no name", line_idx + 1, token)),
+                    }
+                } else if values.insert(token.to_string(), Vec::new()).is_some() {
+                    return Err(format!("line {}: {} is there twice", line_idx + 1, token));
+                } else {
+                    current = Some(token.to_string());
+                }
+            }
+        }
+
+        let mut params = EvalParams::default();
+        let mut error = None;
+        params.visit_mut(&mut |name, slot| {
+            if let Some(new_values) = values.remove(name) {
+                if new_values.len() == slot.len() {
+                    slot.copy_from_slice(&new_values);
+                } else if error.is_none() {
+                    error = Some(format!(
+                        "{} needs {} values, but has {}",
+                        name,
+                        slot.len(),
+                        new_values.len()
+                    ));
+                }
+            }
+        });
+
+        if let Some(error) = error {
+            return Err(error);
+        }
+        if let Some(name) = values.keys().next() {
+            return Err(format!("there's no parameter called {}", name));
+        }
+        Ok(params)
+    }
+
+    pub fn load(path: &str) -> Result<EvalParams, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        EvalParams::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+fn visit_tapered(f: &mut dyn FnMut(&str, &mut [i32]), name: &str, tapered: &mut Tapered) {
+    let mut values = [tapered.mg, tapered.eg];
+    f(name, &mut values);
+    *tapered = Tapered::new(values[0], values[1]);
+}
+
+impl fmt::Display for EvalParams {
+    /// Writes the parameters so that `EvalParams::parse` can read them back. Piece square tables
+    /// are laid out like the board, with a8 first.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut result = Ok(());
+        self.clone().visit_mut(&mut |name, values| {
+            if result.is_err() {
+                return;
+            }
+            result = if values.len() == 64 {
+                writeln!(f, "{}", name).and_then(|_| {
+                    values.chunks(8).try_for_each(|row| {
+                        let row: Vec<String> = row.iter().map(|v| format!("{:>5}", v)).collect();
+                        writeln!(f, "   {}", row.join(""))
+                    })
+                })
+            } else {
+                let values: Vec<String> = values.iter().map(i32::to_string).collect();
+                writeln!(f, "{} {}", name, values.join(" "))
+            };
+        });
+        result
+    }
+}
+
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+/// The parameters the AI plays with: the ones set at startup, or else the defaults
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(EvalParams::default)
+}
+
+/// Sets the parameters the AI plays with for the rest of the run. This has to happen before they
+/// are first used, so it returns false if it's too late.
+pub fn set_params(params: EvalParams) -> bool {
+    PARAMS.set(params).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_round_trips() {
+        let defaults = EvalParams::default();
+        assert_eq!(EvalParams::parse(&defaults.to_string()), Ok(defaults));
+
+        // every value different, and some negative, so nothing can end up in the wrong place
+        let mut params = EvalParams::default();
+        let mut next = -1000;
+        params.visit_mut(&mut |_, values| {
+            for value in values.iter_mut() {
+                *value = next;
+                next += 7;
+            }
+        });
+        assert_eq!(EvalParams::parse(&params.to_string()), Ok(params));
+
+        // the phase weights and mobility baselines are written out, and read back, too
+        let text = "phase_weights 0 2 2 3 5 0\nmobility_base 0 3 6 6 12 0\n";
+        let params = EvalParams::parse(text).unwrap();
+        assert_eq!(params.phase_weights, [0, 2, 2, 3, 5, 0]);
+        assert_eq!(params.mobility_base, [0, 3, 6, 6, 12, 0]);
+        assert!(params.to_string().contains(text));
+        assert_eq!(EvalParams::parse(&params.to_string()), Ok(params));
+    }
+
+    #[test]
+    fn parses_partial_files() {
+        let text = "# only the doubled pawn penalty\ndoubled -5\n  -9 # both phases\n";
+        let params = EvalParams::parse(text).unwrap();
+        assert_eq!(params.doubled, Tapered::new(-5, -9));
+        assert_eq!(
+            EvalParams { doubled: EvalParams::default().doubled, ..params },
+            EvalParams::default()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        for text in ["doubled 1 2 3", "tripled 1 2", "doubled 1 2 doubled 3 4", "5 doubled 1 2"] {
+            assert!(EvalParams::parse(text).is_err(), "{:?} was accepted", text);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// The chess rules and the AI, without the window. The `chess` program adds the GUI on top (and
+// needs the `gui` feature, which is on by default), while everything else, like `tune`, can be
+// built without any of the GUI's dependencies.
+
+pub mod ai;
+pub mod attacks;
+pub mod bitboard;
+pub mod chessboard;
+pub mod cli;
+pub mod eval;
+pub mod eval_params;
+pub mod logger;
+pub mod mate;
+mod move_order;
+pub mod nnue;
+pub mod piece;
+mod see;
+pub mod skill;
+pub mod table;
+pub mod tune;
+pub mod uci;
+
+pub const BOARD_SIZE: u8 = 8;
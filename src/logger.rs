@@ -0,0 +1,59 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// Diagnostics go through the `log` facade (`log::info!` and friends), and end up here. They're
+// written to stderr unless `--log-file` says otherwise, so they never get mixed up with what
+// the UCI protocol or the other commands print to stdout.
+
+struct Logger {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // there's nowhere left to report a failure to write a log message
+        if let Ok(mut output) = self.output.lock() {
+            let _ = writeln!(output, "[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut output) = self.output.lock() {
+            let _ = output.flush();
+        }
+    }
+}
+
+/// Sends every message at `level` or more important to `path` (appending to it), or to stderr if
+/// there's no `path`. `level` is one of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub fn init(level: &str, path: Option<&str>) -> Result<(), String> {
+    let level: LevelFilter = level
+        .parse()
+        .map_err(|_| format!("{} isn't a log level", level))?;
+    let output: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(
+            File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path, e))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+
+    let logger = Box::leak(Box::new(Logger {
+        output: Mutex::new(output),
+    }));
+    log::set_logger(logger).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -2,10 +2,6 @@ extern crate glutin_window;
 extern crate graphics;
 extern crate opengl_graphics;
 extern crate piston;
-extern crate rayon;
-#[macro_use]
-extern crate lazy_static;
-extern crate rand;
 
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::*;
@@ -13,25 +9,102 @@ use piston::event_loop::*;
 use piston::input::*;
 use piston::window::WindowSettings;
 
-mod ai;
-mod chessboard;
 mod chessboard_controller;
 mod chessboard_view;
-mod piece;
 mod sidebar;
-mod table;
 
-use crate::chessboard::Chessboard;
 use crate::chessboard_controller::ChessboardController;
 use crate::chessboard_view::{ChessboardView, ChessboardViewSettings};
 use crate::sidebar::Sidebar;
+use chess::chessboard::{Chessboard, STARTPOS};
+use chess::cli::{fail, take_flag};
+use chess::eval::Evaluator;
+use chess::{cli, eval, eval_params, mate, nnue, skill, uci};
 
-pub const BOARD_SIZE: u8 = 8;
 pub const BOARD_BORDER_SIZE: f64 = 5.0;
 pub const WIDTH: f64 = 600.0;
 pub const HEIGHT: f64 = 400.0 + 2.0 * BOARD_BORDER_SIZE;
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // logging, `--params` and `--net`, which the `tune` program takes too
+    cli::take_common_options(&mut args);
+
+    // `--skill <0-20>` or `--elo <rating>` weakens the AI in the window (see `skill.rs`)
+    let mut skill_level = skill::MAX_SKILL;
+    if let Some(i) = args.iter().position(|arg| arg == "--skill") {
+        let level: u8 = args
+            .get(i + 1)
+            .and_then(|level| level.parse().ok())
+            .unwrap_or_else(|| fail("--skill needs a level from 0 to 20"));
+        skill_level = level.min(skill::MAX_SKILL);
+        args.drain(i..=i + 1);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--elo") {
+        let elo = args
+            .get(i + 1)
+            .and_then(|elo| elo.parse().ok())
+            .unwrap_or_else(|| fail("--elo needs a rating"));
+        skill_level = skill::skill_for_elo(elo);
+        args.drain(i..=i + 1);
+    }
+
+    // These commands run without opening a window:
+    // `chess uci` runs the AI as a UCI engine
+    // `chess eval [fen]` prints the evaluation of a position (or the starting one), term by term
+    // `chess params` prints the evaluation parameters, in the format `--params` reads
+    // `chess mate <n> [--checks] [fen]` looks for a mate in n, only trying checks with `--checks`
+    // `chess mates <problems> [--checks]` runs the mate solver on a file of problems (see
+    // `mate::run_problems`)
+    // Tuning the evaluation parameters is a program of its own, `tune` (see `src/bin/tune.rs`).
+    match args.get(1).map(String::as_str) {
+        Some("uci") => {
+            uci::run();
+            return;
+        }
+        Some("eval") => {
+            let fen = if args.len() > 2 { args[2..].join(" ") } else { STARTPOS.to_string() };
+            let chessboard = read_fen(&fen);
+            println!("{}", eval::evaluate_explained(&chessboard, eval_params::params()));
+            if let Some(network) = nnue::network() {
+                let score = nnue::Nnue::new(network).evaluate(&chessboard);
+                println!("NNUE score {} (from White's point of view)", score);
+            }
+            return;
+        }
+        Some("params") => {
+            print!("{}", eval_params::params());
+            return;
+        }
+        Some("mate") => {
+            let checks_only = take_flag(&mut args, "--checks");
+            let moves = args
+                .get(2)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or_else(|| fail("mate needs a number of moves"));
+            let fen = if args.len() > 3 { args[3..].join(" ") } else { STARTPOS.to_string() };
+            match mate::solve(&read_fen(&fen), moves, checks_only) {
+                Some(line) => {
+                    let length = mate::mate_length(&line);
+                    println!("Mate in {}: {}", length, mate::line_to_string(&line));
+                }
+                None => println!("No mate in {}", moves),
+            }
+            return;
+        }
+        Some("mates") => {
+            let checks_only = take_flag(&mut args, "--checks");
+            let problems = args.get(2).unwrap_or_else(|| fail("mates needs a file of problems"));
+            if let Err(e) = mate::run_problems(problems, checks_only) {
+                fail(format!("Couldn't run the mate problems: {}", e));
+            }
+            return;
+        }
+        Some(command) => fail(format!("{} isn't a command", command)),
+        None => {}
+    }
+
     // Change this to OpenGL::V2_1 if not working.
     let opengl = OpenGL::V3_2;
 
@@ -41,15 +114,14 @@ fn main() {
         .exit_on_esc(true)
         .resizable(false)
         .build()
-        .unwrap();
+        .unwrap_or_else(|e| fail(format!("Couldn't open a window: {}", e)));
 
     //let chessboard = Chessboard::from_fen(String::from("8/8/8/3K4/8/8/1r3k2/6r1 w - - 0 1"));
-    let chessboard = Chessboard::from_fen(String::from(
-        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
-    ));
+    let chessboard = Chessboard::from_fen(String::from(STARTPOS));
     let view_settings = { ChessboardViewSettings::new() };
     let view = ChessboardView::new(view_settings);
     let mut controller = ChessboardController::new(chessboard);
+    controller.ai_options.skill_level = skill_level;
     controller.init_piece_rects();
 
     let sidebar_size = WIDTH - HEIGHT;
@@ -62,7 +134,7 @@ fn main() {
         (),
         TextureSettings::new().filter(Filter::Linear),
     )
-    .unwrap();
+    .unwrap_or_else(|e| fail(format!("Couldn't load the font: {}", e)));
 
     // Create a new game and run it.
     let mut gl = GlGraphics::new(opengl);
@@ -82,3 +154,8 @@ fn main() {
         }
     }
 }
+
+/// The position in `fen`, which came from the command line
+fn read_fen(fen: &str) -> Chessboard {
+    Chessboard::try_from_fen(fen).unwrap_or_else(|e| fail(format!("Invalid FEN {}: {}", fen, e)))
+}
@@ -0,0 +1,220 @@
+use crate::chessboard::Chessboard;
+use crate::piece::Piece;
+use crate::table::TTMove;
+use std::collections::HashSet;
+use std::fs;
+use std::time::Instant;
+
+// A mate solver: unlike the normal search, it doesn't evaluate anything. It just proves that the
+// side to move can mate within some number of moves whatever the other side does (or that it
+// can't). The attacker only needs one move that works, but every one of the defender's replies
+// has to be answered. Like the rest of the AI, pawns only ever promote to queens, so mates that
+// need an underpromotion aren't found.
+
+struct MateSolver {
+    /// Only try checking moves for the attacker, which is much quicker, but misses mates with a
+    /// quiet move in them
+    checks_only: bool,
+    nodes: u64,
+    /// Attacking positions (and how many moves they had) where we've already shown there's no
+    /// mate, since the same position can often be reached by playing the moves in another order
+    refuted: HashSet<(u64, u32)>,
+}
+
+/// Looks for a mate in at most `moves` moves for the side to move, and returns the mating line if
+/// there is one. A shorter mate is always found before a longer one. In the line, the defender
+/// always plays the reply that holds out the longest.
+pub fn solve(chessboard: &Chessboard, moves: u32, checks_only: bool) -> Option<Vec<TTMove>> {
+    let start = Instant::now();
+    let mut solver = MateSolver {
+        checks_only,
+        nodes: 0,
+        refuted: HashSet::new(),
+    };
+
+    let mut line = None;
+    for moves in 1..=moves {
+        line = solver.attack(chessboard, moves);
+        if line.is_some() {
+            break;
+        }
+    }
+    log::info!(
+        "Mate search: {} nodes, {} ms",
+        solver.nodes,
+        start.elapsed().as_millis()
+    );
+    line
+}
+
+impl MateSolver {
+    /// The attacker is to move, and has to mate in at most `moves` moves
+    fn attack(&mut self, chessboard: &Chessboard, moves: u32) -> Option<Vec<TTMove>> {
+        if self.refuted.contains(&(chessboard.hash(), moves)) {
+            return None;
+        }
+
+        let mut children = Vec::new();
+        for m in chessboard.possible_moves(chessboard.turn) {
+            let mut temp = chessboard.clone();
+            temp.try_move(m.0, m.1, Some(&Piece::Queen));
+            self.nodes += 1;
+
+            let check = temp.checkers() != 0;
+            // the last move has to give check, or it can't be mate
+            if !check && (self.checks_only || moves == 1) {
+                continue;
+            }
+            children.push((TTMove::new(m.0, m.1), temp, check));
+        }
+        // checks are by far the most likely to force mate
+        children.sort_by_key(|&(_, _, check)| !check);
+
+        // a mate right away beats any longer one
+        for (m, temp, check) in children.iter() {
+            if *check && temp.possible_moves(temp.turn).is_empty() {
+                return Some(vec![*m]);
+            }
+        }
+
+        if moves > 1 {
+            for (m, temp, _) in children.iter() {
+                if let Some(line) = self.defend(temp, moves - 1) {
+                    let mut full_line = vec![*m];
+                    full_line.extend(line);
+                    return Some(full_line);
+                }
+            }
+        }
+
+        self.refuted.insert((chessboard.hash(), moves));
+        None
+    }
+
+    /// The defender is to move, and the attacker has to mate in at most `moves` moves after
+    /// whichever reply it makes
+    fn defend(&mut self, chessboard: &Chessboard, moves: u32) -> Option<Vec<TTMove>> {
+        let replies = chessboard.possible_moves(chessboard.turn);
+        // no moves, and `attack` would already have seen if it was mate, so it's stalemate
+        if replies.is_empty() {
+            return None;
+        }
+
+        let mut longest: Option<Vec<TTMove>> = None;
+        for m in replies {
+            let mut temp = chessboard.clone();
+            temp.try_move(m.0, m.1, Some(&Piece::Queen));
+            self.nodes += 1;
+
+            // one reply the attacker can't mate after is enough to refute the whole thing
+            let line = self.attack(&temp, moves)?;
+            if longest.as_ref().is_none_or(|longest| line.len() + 1 > longest.len()) {
+                let mut full_line = vec![TTMove::new(m.0, m.1)];
+                full_line.extend(line);
+                longest = Some(full_line);
+            }
+        }
+        longest
+    }
+}
+
+/// Checks the solver against every problem in the `path` file, and returns how many it got
+/// right. Each line is a FEN followed by `#N` for a mate in N, like
+/// `6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 #1`. A problem only counts as solved if the solver finds a
+/// mate in N, and doesn't find one any quicker.
+pub fn run_problems(path: &str, checks_only: bool) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut solved = 0;
+    let mut total = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        let moves = match tokens.last().and_then(|t| t.strip_prefix('#')) {
+            Some(moves) if tokens.len() >= 5 => moves
+                .parse::<u32>()
+                .map_err(|_| format!("{}: line {} doesn't end in #N", path, i + 1))?,
+            _ => return Err(format!("{}: line {} isn't a FEN followed by #N", path, i + 1)),
+        };
+        let fen = tokens[..tokens.len() - 1].join(" ");
+        let chessboard = Chessboard::try_from_fen(&fen)
+            .map_err(|e| format!("{}: line {} has an invalid FEN ({})", path, i + 1, e))?;
+        total += 1;
+
+        let result = match solve(&chessboard, moves, checks_only) {
+            Some(line) if mate_length(&line) == moves => {
+                solved += 1;
+                format!("solved: {}", line_to_string(&line))
+            }
+            Some(line) => format!("FAILED, found a quicker mate: {}", line_to_string(&line)),
+            None => String::from("FAILED, didn't find the mate"),
+        };
+        println!("{} #{}: {}", fen, moves, result);
+    }
+
+    println!("Solved {} of {} problems", solved, total);
+    Ok(solved)
+}
+
+/// How many moves the attacker makes in a mating line
+pub fn mate_length(line: &[TTMove]) -> u32 {
+    (line.len() as u32).div_ceil(2)
+}
+
+pub fn line_to_string(line: &[TTMove]) -> String {
+    let moves: Vec<String> = line.iter().map(|m| m.to_string()).collect();
+    moves.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chessboard::STARTPOS;
+
+    const PROBLEMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/mate_problems.txt");
+
+    #[test]
+    fn solves_every_problem() {
+        let text = fs::read_to_string(PROBLEMS).unwrap();
+        let problems = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .count();
+        assert_eq!(run_problems(PROBLEMS, false), Ok(problems));
+        // one of them needs a quiet move
+        assert_eq!(run_problems(PROBLEMS, true), Ok(problems - 1));
+    }
+
+    #[test]
+    fn finds_the_shortest_mate() {
+        // allowed up to three moves, it still finds the back rank mate in one
+        let chessboard = Chessboard::from_fen(String::from("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"));
+        let line = solve(&chessboard, 3, false).unwrap();
+        assert_eq!(line_to_string(&line), "a1a8");
+    }
+
+    #[test]
+    fn no_mate_from_the_start() {
+        let chessboard = Chessboard::from_fen(String::from(STARTPOS));
+        assert!(solve(&chessboard, 2, false).is_none());
+    }
+
+    #[test]
+    fn reports_bad_problems() {
+        let name = format!("bad_mate_problems_{}.txt", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let problems = [
+            ("6k1/5ppp/8/8/8/8/8/R5K1 w - - #1\n6k1/5ppp/8/8/8/8/R5K1 w - - #1\n", "line 2"),
+            ("# a comment\n6k1/5ppp/8/8/8/8/8/R5K1 w - #1\n", "line 2"),
+            ("6k1/5ppp/8/8/8/8/8/R5K1 w - - #x\n", "line 1"),
+        ];
+        for (text, line) in problems.iter() {
+            fs::write(&path, text).unwrap();
+            let error = run_problems(path.to_str().unwrap(), false).unwrap_err();
+            assert!(error.contains(line), "{}", error);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use crate::ai::{piece_value, MAX_PLY};
+use crate::attacks::square_idx;
+use crate::chessboard::Chessboard;
+use crate::piece::Piece;
+use crate::table::TTMove;
+
+/// Once a history score grows past this, the whole table is halved, so that old cutoffs slowly
+/// matter less than new ones, and quiet moves never outrank the killers.
+const HISTORY_LIMIT: i32 = 50_000;
+
+// Moves are tried in this order: the best move from the TranspositionTable, captures and
+// promotions that don't lose material (by MVV-LVA), the two killer moves, the rest of the quiet
+// moves by their history score, and finally captures that lose material according to SEE.
+const TT_MOVE_SCORE: i32 = 1_000_000;
+const CAPTURE_SCORE: i32 = 500_000;
+const KILLER_SCORES: [i32; 2] = [200_000, 100_000];
+const LOSING_CAPTURE_SCORE: i32 = -500_000;
+
+/// Move ordering information gathered during a search. Every search thread owns its own copy, so
+/// nothing here needs to be locked.
+pub struct SearchTables {
+    /// Two quiet moves per ply that recently caused a beta cutoff. A move that refutes one
+    /// position is likely to refute its siblings too.
+    killers: Vec<[Option<TTMove>; 2]>,
+    /// How often moving a piece (by `piece_id`) to a square has caused a beta cutoff, weighted by
+    /// depth.
+    history: [[i32; 64]; 12],
+}
+
+impl SearchTables {
+    pub fn new() -> SearchTables {
+        SearchTables {
+            killers: vec![[None, None]; MAX_PLY],
+            history: [[0; 64]; 12],
+        }
+    }
+
+    /// Sorts `moves` so the ones most likely to be good are searched first.
+    pub fn order_moves(
+        &self,
+        chessboard: &Chessboard,
+        moves: &mut [(&Piece, [u8; 2])],
+        tt_move: Option<TTMove>,
+        ply: usize,
+    ) {
+        moves.sort_by_cached_key(|m| -self.move_score(chessboard, *m, tt_move, ply));
+    }
+
+    fn move_score(
+        &self,
+        chessboard: &Chessboard,
+        m: (&Piece, [u8; 2]),
+        tt_move: Option<TTMove>,
+        ply: usize,
+    ) -> i32 {
+        let tt_m = TTMove::new(m.0, m.1);
+        if tt_move == Some(tt_m) {
+            return TT_MOVE_SCORE;
+        }
+        if let Some(victim) = mvv_lva(chessboard, m) {
+            return if chessboard.see(m) < 0 {
+                LOSING_CAPTURE_SCORE + victim
+            } else {
+                CAPTURE_SCORE + victim
+            };
+        }
+        if let Some(killers) = self.killers.get(ply) {
+            for (killer, score) in killers.iter().zip(&KILLER_SCORES) {
+                if *killer == Some(tt_m) {
+                    return *score;
+                }
+            }
+        }
+        self.history[tt_m.piece_id][square_idx(tt_m.end_pos)]
+    }
+
+    /// Records that the quiet move `m` caused a beta cutoff at `ply`, with `depth` plies left.
+    pub fn record_cutoff(&mut self, m: TTMove, depth: u8, ply: usize) {
+        if let Some(killers) = self.killers.get_mut(ply) {
+            if killers[0] != Some(m) {
+                killers[1] = killers[0];
+                killers[0] = Some(m);
+            }
+        }
+
+        let entry = &mut self.history[m.piece_id][square_idx(m.end_pos)];
+        *entry += i32::from(depth) * i32::from(depth);
+        if *entry > HISTORY_LIMIT {
+            for score in self.history.iter_mut().flatten() {
+                *score /= 2;
+            }
+        }
+    }
+}
+
+/// Most Valuable Victim, Least Valuable Attacker. Returns `None` if `m` isn't a capture or a
+/// promotion. Otherwise, captures of bigger pieces come first, and between those, captures with
+/// smaller pieces.
+pub fn mvv_lva(chessboard: &Chessboard, m: (&Piece, [u8; 2])) -> Option<i32> {
+    let (piece, end_pos) = m;
+    let is_pawn = matches!(piece, Piece::Pawn(_));
+    let victim = match chessboard.piece_at(end_pos) {
+        Some(captured) => piece_value(captured),
+        // a pawn moving diagonally to an empty square is en passant
+        None if is_pawn && piece.data().position[0] != end_pos[0] => piece_value(piece),
+        None => 0,
+    };
+    let promotion = is_pawn && end_pos[1] == piece.data().side.other().back_rank();
+
+    if victim == 0 && !promotion {
+        return None;
+    }
+
+    // the king is worth 0, but it's the last piece we'd want to capture with
+    let attacker = match piece {
+        Piece::King(_) => 1000,
+        _ => piece_value(piece),
+    };
+    let promotion_bonus = if promotion { 100_000 } else { 0 };
+    Some(promotion_bonus + victim * 100 - attacker)
+}
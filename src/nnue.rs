@@ -0,0 +1,238 @@
+use crate::ai::MATE_BOUND;
+use crate::chessboard::Chessboard;
+use crate::eval::Evaluator;
+use crate::piece::{kind_idx, side_idx, Piece, Side};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::sync::OnceLock;
+
+// A small NNUE ("efficiently updatable neural network") evaluation. Each side has its own view of
+// the board: 768 inputs, one for each kind of piece (ours or theirs) on each square, with the
+// board flipped for Black so that "ours" always starts at the bottom. Both views go through the
+// same `HIDDEN` neurons. The side to move's neurons and then the other side's are clipped to
+// [0, QA] and fed to a single output.
+//
+// The sums into the hidden neurons are the accumulator. A move only turns a few inputs on or off,
+// so the chessboard keeps the accumulator up to date as pieces are added and removed, instead of
+// summing every input again for each evaluation. It's all plain scalar code, without any SIMD
+// intrinsics, so it runs on any CPU (and the compiler is free to vectorise the loops itself).
+//
+// Network files are little endian: the magic bytes `MAGIC`, the number of hidden neurons as a
+// u32 (which has to be `HIDDEN`), and then i16s for the input weights (`HIDDEN` for each input in
+// turn), the hidden biases and the output weights (the side to move's first), and finally an i32
+// for the output bias.
+
+pub const HIDDEN: usize = 128;
+const INPUTS: usize = 768;
+const MAGIC: &[u8; 8] = b"CHESSNN1";
+
+/// The quantisation of the hidden layer: 1.0 is stored as `QA`
+const QA: i32 = 255;
+/// The quantisation of the output weights: 1.0 is stored as `QB`
+const QB: i32 = 64;
+/// The network's output is multiplied by this to get centipawns
+const SCALE: i32 = 400;
+
+pub struct Network {
+    input_weights: Vec<[i16; HIDDEN]>,
+    hidden_biases: [i16; HIDDEN],
+    output_weights: [[i16; HIDDEN]; 2],
+    output_bias: i32,
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network, String> {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Network::parse(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Network, String> {
+        let expected_len = MAGIC.len() + 4 + 2 * (INPUTS * HIDDEN + 3 * HIDDEN) + 4;
+        if !bytes.starts_with(MAGIC) {
+            return Err(String::from("not a network file"));
+        }
+        if bytes.len() != expected_len {
+            return Err(format!("should be {} bytes long, but is {}", expected_len, bytes.len()));
+        }
+
+        let mut reader = &bytes[MAGIC.len()..];
+        let mut take = |len: usize| {
+            let (head, tail) = reader.split_at(len);
+            reader = tail;
+            head
+        };
+        let hidden = u32::from_le_bytes(take(4).try_into().unwrap());
+        if hidden as usize != HIDDEN {
+            return Err(format!("has {} hidden neurons, but we need {}", hidden, HIDDEN));
+        }
+        let mut read_i16s = |values: &mut [i16]| {
+            let bytes = take(2 * values.len());
+            for (value, bytes) in values.iter_mut().zip(bytes.chunks(2)) {
+                *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        };
+
+        let mut network = Network {
+            input_weights: vec![[0; HIDDEN]; INPUTS],
+            hidden_biases: [0; HIDDEN],
+            output_weights: [[0; HIDDEN]; 2],
+            output_bias: 0,
+        };
+        for weights in network.input_weights.iter_mut() {
+            read_i16s(weights);
+        }
+        read_i16s(&mut network.hidden_biases);
+        read_i16s(&mut network.output_weights[0]);
+        read_i16s(&mut network.output_weights[1]);
+        network.output_bias = i32::from_le_bytes(take(4).try_into().unwrap());
+        Ok(network)
+    }
+
+    /// The evaluation in centipawns, from the point of view of `turn`
+    fn output(&self, accumulator: &Accumulator, turn: Side) -> i32 {
+        let us = side_idx(turn);
+        let views = [&accumulator.values[us], &accumulator.values[1 - us]];
+        // with large enough weights, this doesn't fit in an i32, even before it's scaled
+        let mut sum: i64 = 0;
+        for (view, weights) in views.iter().zip(self.output_weights.iter()) {
+            for (&value, &weight) in view.iter().zip(weights.iter()) {
+                sum += i64::from(i32::from(value).clamp(0, QA) * i32::from(weight));
+            }
+        }
+        let score = (sum + i64::from(self.output_bias)) * i64::from(SCALE) / i64::from(QA * QB);
+        // however sure of itself the network is, it mustn't look like a mate
+        score.clamp(i64::from(1 - MATE_BOUND), i64::from(MATE_BOUND - 1)) as i32
+    }
+}
+
+/// The input for `piece` on `pos`, as seen by `view`
+fn input_idx(view: Side, pos: [u8; 2], piece: &Piece) -> usize {
+    let data = piece.data();
+    let whose = if data.side == view { 0 } else { 1 };
+    let rank = match view {
+        Side::Light => pos[1],
+        Side::Dark => 7 - pos[1],
+    };
+    whose * 384 + kind_idx(piece) * 64 + (rank * 8 + pos[0]) as usize
+}
+
+/// The hidden neurons' sums for both sides' views, before they're clipped. Indexed by `side_idx`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Accumulator {
+    values: [[i16; HIDDEN]; 2],
+}
+
+impl Accumulator {
+    pub fn new(network: &Network, pieces: &HashMap<[u8; 2], Piece>) -> Accumulator {
+        let mut accumulator = Accumulator {
+            values: [network.hidden_biases; 2],
+        };
+        for (&pos, piece) in pieces {
+            accumulator.add(network, pos, piece);
+        }
+        accumulator
+    }
+
+    pub fn add(&mut self, network: &Network, pos: [u8; 2], piece: &Piece) {
+        for view in [Side::Light, Side::Dark] {
+            let weights = &network.input_weights[input_idx(view, pos, piece)];
+            for (value, &weight) in self.values[side_idx(view)].iter_mut().zip(weights.iter()) {
+                *value = value.wrapping_add(weight);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, network: &Network, pos: [u8; 2], piece: &Piece) {
+        for view in [Side::Light, Side::Dark] {
+            let weights = &network.input_weights[input_idx(view, pos, piece)];
+            for (value, &weight) in self.values[side_idx(view)].iter_mut().zip(weights.iter()) {
+                *value = value.wrapping_sub(weight);
+            }
+        }
+    }
+}
+
+/// Evaluates positions with the network
+pub struct Nnue {
+    network: &'static Network,
+}
+
+impl Nnue {
+    pub fn new(network: &'static Network) -> Nnue {
+        Nnue { network }
+    }
+}
+
+impl Evaluator for Nnue {
+    fn evaluate(&mut self, chessboard: &Chessboard) -> i32 {
+        let score = match chessboard.accumulator() {
+            Some(accumulator) => self.network.output(accumulator, chessboard.turn),
+            // the board was set up before the network was loaded
+            None => {
+                let accumulator = Accumulator::new(self.network, chessboard.pieces());
+                self.network.output(&accumulator, chessboard.turn)
+            }
+        };
+        match chessboard.turn {
+            Side::Light => score,
+            Side::Dark => -score,
+        }
+    }
+}
+
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+/// The network loaded with `--net` (or the UCI `EvalFile` option), if there is one
+#[inline(always)]
+pub fn network() -> Option<&'static Network> {
+    NETWORK.get()
+}
+
+/// Sets the network everything uses from now on. Chessboards keep their accumulators for the
+/// network that was loaded when they were set up, so this can only be done once, and returns false
+/// if there already was a network.
+pub fn set_network(network: Network) -> bool {
+    NETWORK.set(network).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extreme_weights_dont_overflow() {
+        // every hidden neuron is clipped at QA, and every output weight is as large as it can be
+        let network = Network {
+            input_weights: vec![[0; HIDDEN]; INPUTS],
+            hidden_biases: [i16::MAX; HIDDEN],
+            output_weights: [[i16::MAX; HIDDEN]; 2],
+            output_bias: i32::MAX,
+        };
+        let accumulator = Accumulator::new(&network, &HashMap::new());
+        let score = network.output(&accumulator, Side::Light);
+        assert_eq!(score, MATE_BOUND - 1);
+
+        let network = Network {
+            output_weights: [[i16::MIN; HIDDEN]; 2],
+            output_bias: i32::MIN,
+            ..network
+        };
+        assert_eq!(network.output(&accumulator, Side::Dark), 1 - MATE_BOUND);
+    }
+
+    #[test]
+    fn rejects_bad_networks() {
+        let len = MAGIC.len() + 4 + 2 * (INPUTS * HIDDEN + 3 * HIDDEN) + 4;
+        let mut bytes = vec![0; len];
+        bytes[..MAGIC.len()].copy_from_slice(MAGIC);
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(HIDDEN as u32).to_le_bytes());
+        assert!(Network::parse(&bytes).is_ok());
+
+        let error = |bytes: &[u8]| Network::parse(bytes).err().unwrap();
+        assert_eq!(error(&bytes[1..]), "not a network file");
+        assert!(error(&bytes[..len - 1]).contains("bytes long"));
+        bytes[MAGIC.len()] = 64;
+        assert!(error(&bytes).contains("has 64 hidden neurons"));
+    }
+}
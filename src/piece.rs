@@ -1,3 +1,4 @@
+use crate::bitboard::square_bit;
 use crate::chessboard::{CastleRights, Chessboard};
 use crate::BOARD_SIZE;
 
@@ -24,6 +25,15 @@ impl Side {
     }
 }
 
+/// Light, dark
+#[inline(always)]
+pub fn side_idx(side: Side) -> usize {
+    match side {
+        Side::Light => 0,
+        Side::Dark => 1,
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct PieceData {
     pub position: [u8; 2],
@@ -90,7 +100,7 @@ impl Piece {
             | Piece::Knight(data)
             | Piece::Bishop(data)
             | Piece::Queen(data)
-            | Piece::King(data) => &data,
+            | Piece::King(data) => data,
         }
     }
 
@@ -201,7 +211,7 @@ impl Piece {
                 let (dx, dy) = Piece::dx_dy(data.position, end_pos);
                 if dx == 0
                     && dy == pawn_settings::direction(data.side)
-                    && chessboard.piece_at(end_pos) == None
+                    && chessboard.piece_at(end_pos).is_none()
                 {
                     // regular move forward
                     if end_pos[1] == data.side.other().back_rank() {
@@ -215,8 +225,9 @@ impl Piece {
                     && chessboard.piece_at([
                         data.position[0],
                         (data.position[1] as i8 + pawn_settings::direction(data.side)) as u8,
-                    ]) == None
-                    && chessboard.piece_at(end_pos) == None
+                    ])
+                    .is_none()
+                    && chessboard.piece_at(end_pos).is_none()
                 {
                     // doublestep at beginning
                     MoveType::Doublestep
@@ -304,7 +315,7 @@ impl Piece {
                         // clone.
                         let mut temp_chessboard = chessboard.clone();
                         temp_chessboard.insert(data.position, self.clone());
-                        if temp_chessboard.is_king_in_check(&self) {
+                        if temp_chessboard.is_king_in_check(self) {
                             return MoveType::Invalid;
                         }
                     }
@@ -332,6 +343,17 @@ impl Piece {
         };
 
         if check_check && original_move_type != MoveType::Invalid {
+            // If our king isn't in check, only moving the king, a pinned piece, or capturing en
+            // passant (which takes two pieces off the same rank) can put it in check.
+            let side = self.data().side;
+            let can_expose_king = matches!(self, Piece::King(_))
+                || original_move_type == MoveType::EnPassant
+                || chessboard.pinned_pieces(side) & square_bit(self.data().position) != 0
+                || chessboard.king(side).is_none_or(|king| chessboard.is_king_in_check(king));
+            if !can_expose_king {
+                return original_move_type;
+            }
+
             // clone the chessboard, pretend to apply this move, and check if the king
             // is in check
             let mut temp_board = chessboard.clone();
@@ -358,3 +380,24 @@ pub enum Piece {
     Queen(PieceData),
     King(PieceData),
 }
+
+// The kinds of piece, in the order tables indexed by `kind_idx` use
+pub const PAWN: usize = 0;
+pub const KNIGHT: usize = 1;
+pub const BISHOP: usize = 2;
+pub const ROOK: usize = 3;
+pub const QUEEN: usize = 4;
+pub const KING: usize = 5;
+
+/// Pawn, knight, bishop, rook, queen, king
+#[inline(always)]
+pub fn kind_idx(piece: &Piece) -> usize {
+    match piece {
+        Piece::Pawn(_) => PAWN,
+        Piece::Knight(_) => KNIGHT,
+        Piece::Bishop(_) => BISHOP,
+        Piece::Rook(_) => ROOK,
+        Piece::Queen(_) => QUEEN,
+        Piece::King(_) => KING,
+    }
+}
@@ -0,0 +1,129 @@
+use crate::attacks::square_idx;
+use crate::bitboard::{square_bit, Bitboards};
+use crate::chessboard::Chessboard;
+use crate::piece::{kind_idx, side_idx, Piece, Side, KING, PAWN, QUEEN};
+
+/// The same as `piece_value`, except the king is worth more than everything else put together, so
+/// it only ever captures last.
+const SEE_VALUES: [i32; 6] = [100, 300, 300, 500, 900, 20_000];
+
+impl Chessboard {
+    /// Static exchange evaluation: how much material the side making the capture `m` comes out
+    /// ahead by (in centipawns), once both sides have made every recapture on that square that
+    /// pays off, always recapturing with their least valuable piece. Sliders lined up behind
+    /// other attackers (x-rays) join in once the pieces in front of them have captured. Pins
+    /// aren't taken into account.
+    pub fn see(&self, m: (&Piece, [u8; 2])) -> i32 {
+        let (piece, end_pos) = m;
+        let data = piece.data();
+        let bitboards = self.bitboards();
+        let target = square_idx(end_pos);
+        let mut occupied = bitboards.occupied ^ square_bit(data.position);
+
+        // gains[i] is what the side making the i-th capture wins, if the exchange stops there
+        let mut gains = [0; 32];
+        gains[0] = match self.piece_at(end_pos) {
+            Some(captured) => SEE_VALUES[kind_idx(captured)],
+            // a pawn moving diagonally to an empty square is en passant
+            None if matches!(piece, Piece::Pawn(_)) && data.position[0] != end_pos[0] => {
+                occupied ^= square_bit([end_pos[0], data.position[1]]);
+                SEE_VALUES[PAWN]
+            }
+            None => 0,
+        };
+        // the piece standing on the square, which the next capture would win
+        let mut on_square = SEE_VALUES[kind_idx(piece)];
+        if matches!(piece, Piece::Pawn(_)) && end_pos[1] == data.side.other().back_rank() {
+            gains[0] += SEE_VALUES[QUEEN] - SEE_VALUES[PAWN];
+            on_square = SEE_VALUES[QUEEN];
+        }
+
+        let mut side = data.side.other();
+        let mut depth = 0;
+        while let Some((bit, kind)) = least_valuable_attacker(bitboards, target, side, occupied) {
+            depth += 1;
+            gains[depth] = on_square - gains[depth - 1];
+            // this capture can't change the outcome, whoever comes out ahead
+            if i32::max(-gains[depth - 1], gains[depth]) < 0 {
+                depth -= 1;
+                break;
+            }
+            occupied ^= bit;
+            on_square = SEE_VALUES[kind];
+            side = side.other();
+        }
+
+        // each side only makes its capture if that's better than stopping the exchange
+        while depth > 0 {
+            gains[depth - 1] = -i32::max(-gains[depth - 1], gains[depth]);
+            depth -= 1;
+        }
+        gains[0]
+    }
+
+    /// The squares of `side`'s pieces that the other side could win material by capturing, if it
+    /// were their move
+    pub fn hanging_pieces(&self, side: Side) -> Vec<[u8; 2]> {
+        let mut hanging: Vec<[u8; 2]> = self
+            .possible_captures(side.other())
+            .into_iter()
+            .filter(|m| self.piece_at(m.1).is_some() && self.see(*m) > 0)
+            .map(|m| m.1)
+            .collect();
+        hanging.sort_unstable();
+        hanging.dedup();
+        hanging
+    }
+}
+
+/// The bit and kind of `side`'s cheapest piece attacking `target`, out of the pieces that are
+/// still in `occupied`
+fn least_valuable_attacker(
+    bitboards: &Bitboards,
+    target: usize,
+    side: Side,
+    occupied: u64,
+) -> Option<(u64, usize)> {
+    let attackers = bitboards.attackers_to(target, side, occupied);
+    let pieces = &bitboards.pieces[side_idx(side)];
+    (PAWN..=KING).find_map(|kind| {
+        let attackers = attackers & pieces[kind];
+        // just the lowest bit
+        (attackers != 0).then(|| (attackers & attackers.wrapping_neg(), kind))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `see` makes of the capture `m` (like `e1e5`) in the position `fen`
+    fn see(fen: &str, m: &str) -> i32 {
+        let chessboard = Chessboard::from_fen(String::from(fen));
+        let square = |s: &[u8]| [s[0] - b'a', s[1] - b'1'];
+        let piece = chessboard.piece_at(square(&m.as_bytes()[0..2])).unwrap();
+        chessboard.see((piece, square(&m.as_bytes()[2..4])))
+    }
+
+    #[test]
+    fn exchanges() {
+        let cases = [
+            // the examples from the Chess Programming Wiki: a rook taking an undefended pawn, and
+            // a knight taking a pawn with more defenders than it has attackers
+            ("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 100),
+            ("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5", -200),
+            // the second white rook only joins in once the first has captured
+            ("4k3/4r3/8/4r3/8/8/4R3/4R1K1 w - - 0 1", "e2e5", 500),
+            // an even trade
+            ("4k3/8/3p4/4r3/8/8/8/4R1K1 w - - 0 1", "e1e5", 0),
+            // the queen is lost for a pawn
+            ("4k3/8/3p4/4p3/8/8/8/4Q1K1 w - - 0 1", "e1e5", -800),
+            // nothing can take back
+            ("4k3/8/8/3n4/4P3/8/8/6K1 w - - 0 1", "e4d5", 300),
+            ("4k3/8/8/3pP3/8/8/8/6K1 w - d6 0 1", "e5d6", 100),
+        ];
+        for (fen, m, value) in cases.iter() {
+            assert_eq!(see(fen, m), *value, "{} in {}", m, fen);
+        }
+    }
+}
@@ -1,13 +1,16 @@
-use graphics;
 use graphics::character::CharacterCache;
 use graphics::math::Matrix2d;
 use graphics::{DrawState, Graphics, Transformed};
 use piston::input::GenericEvent;
 use std::collections::HashMap;
 
-use crate::chessboard::Checkmate;
 use crate::chessboard_controller::{ChessboardController, Rectangle};
-use crate::piece::{Piece, Side};
+use chess::ai::Score;
+use chess::chessboard::Checkmate;
+use chess::piece::{Piece, Side};
+
+/// How many moves of each of the AI's candidate lines are shown
+const SIDEBAR_PV_MOVES: usize = 3;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ButtonIds {
@@ -187,6 +190,51 @@ impl Sidebar {
                 .unwrap_or_else(|_| panic!("Error rendering text")); // somehow, this error doesn't implement Debug.
         }
 
+        if let Some(score) = controller.ai_score {
+            // AI Evaluation
+            let size = 13;
+            let transform = transform.trans(
+                self.rect.left() + 10.0,
+                self.rect.top() + f64::from(size) + 70.0,
+            );
+            Text::new_color(TEXT_COLOR, size)
+                .draw(
+                    &format!("AI: {}", Score(score)),
+                    cache,
+                    draw_state,
+                    transform,
+                    g,
+                )
+                .unwrap_or_else(|_| panic!("Error rendering text")); // somehow, this error doesn't implement Debug.
+        }
+
+        // the AI's candidate moves, when they're turned on (with the L key)
+        if controller.show_lines {
+            for (i, line) in controller.ai_lines.iter().enumerate() {
+                // The AI's candidate moves, with only the start of each line, so it fits
+                let size = 11;
+                let transform = transform.trans(
+                    self.rect.left() + 10.0,
+                    self.rect.top() + 13.0 + 70.0 + f64::from(size + 4) * (i + 1) as f64,
+                );
+                let moves: Vec<String> = line
+                    .moves
+                    .iter()
+                    .take(SIDEBAR_PV_MOVES)
+                    .map(|m| m.to_string())
+                    .collect();
+                Text::new_color(TEXT_COLOR, size)
+                    .draw(
+                        &format!("{}: {}", Score(line.score), moves.join(" ")),
+                        cache,
+                        draw_state,
+                        transform,
+                        g,
+                    )
+                    .unwrap_or_else(|_| panic!("Error rendering text")); // somehow, this error doesn't implement Debug.
+            }
+        }
+
         // Black Check/Checkmate
         // Note that the game_result stores the winner, but we want to display "Checkmate" on the
         // loser
@@ -0,0 +1,128 @@
+use crate::ai::{PvLine, SearchLimits};
+use rand::Rng;
+
+// Playing strength below full strength. A weaker AI searches less (fewer plies and nodes), and
+// instead of always playing the best move, it picks one of the moves that score about as well,
+// and every so often one that's clearly worse. The lower the level, the less it searches, the
+// bigger the gap it's happy to pick from, and the more often it slips up.
+
+/// Full strength: nothing is limited, and the best move is always played
+pub const MAX_SKILL: u8 = 20;
+
+/// Below full strength, the search looks at (at least) this many lines, so it has something to
+/// choose from
+pub const SKILL_MULTI_PV: usize = 4;
+
+/// Roughly how strong levels 0 and `MAX_SKILL` play. The levels in between are spread evenly
+/// between them. These are guesses, not measured against rated opponents.
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+/// How far below the best move's score (in centipawns) a move may be and still get played, for
+/// each level below `MAX_SKILL`
+const MARGIN_PER_LEVEL: i32 = 15;
+
+/// The chance (in percent) of playing any of the lines, however bad, for each level below
+/// `MAX_SKILL`
+const BLUNDER_PERCENT_PER_LEVEL: u32 = 2;
+
+/// The skill level that plays closest to `elo`
+pub fn skill_for_elo(elo: u32) -> u8 {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+    let per_level = (MAX_ELO - MIN_ELO) / u32::from(MAX_SKILL);
+    ((elo - MIN_ELO + per_level / 2) / per_level) as u8
+}
+
+/// `limits`, with the depth and nodes cut down to what `skill` is allowed to search
+pub fn limit_search(limits: &SearchLimits, skill: u8) -> SearchLimits {
+    if skill >= MAX_SKILL {
+        return *limits;
+    }
+    // level 0 only looks one ply ahead, and level 19 ten
+    let depth = skill / 2 + 1;
+    let nodes = 1000 << (skill / 2);
+    SearchLimits {
+        depth: Some(limits.depth.map_or(depth, |d| d.min(depth))),
+        nodes: Some(limits.nodes.map_or(nodes, |n| n.min(nodes))),
+        ..*limits
+    }
+}
+
+/// Which of the `lines` (best first) a player of level `skill` plays
+pub fn pick_line(lines: &[PvLine], skill: u8, rng: &mut impl Rng) -> usize {
+    if skill >= MAX_SKILL || lines.len() < 2 {
+        return 0;
+    }
+    let weakness = MAX_SKILL - skill;
+
+    if rng.gen_range(0, 100) < u32::from(weakness) * BLUNDER_PERCENT_PER_LEVEL {
+        return rng.gen_range(0, lines.len());
+    }
+    let margin = i32::from(weakness) * MARGIN_PER_LEVEL;
+    let close = lines
+        .iter()
+        .take_while(|line| line.score >= lines[0].score - margin)
+        .count();
+    rng.gen_range(0, close)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::GameClock;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::time::Duration;
+
+    #[test]
+    fn elo_maps_onto_the_levels() {
+        assert_eq!(skill_for_elo(0), 0);
+        assert_eq!(skill_for_elo(MIN_ELO), 0);
+        assert_eq!(skill_for_elo(MAX_ELO), MAX_SKILL);
+        assert_eq!(skill_for_elo(u32::MAX), MAX_SKILL);
+        for elo in MIN_ELO..MAX_ELO {
+            assert!(skill_for_elo(elo) <= skill_for_elo(elo + 1), "at {}", elo);
+        }
+    }
+
+    #[test]
+    fn full_strength_isnt_limited() {
+        let limits = SearchLimits {
+            depth: Some(30),
+            nodes: None,
+            movetime: Some(Duration::from_secs(5)),
+            clock: Some(GameClock {
+                time_left: Duration::from_secs(60),
+                increment: Duration::from_secs(1),
+                moves_to_go: None,
+            }),
+        };
+        let limited = format!("{:?}", limit_search(&limits, MAX_SKILL));
+        assert_eq!(limited, format!("{:?}", limits));
+
+        // weaker levels only ever search less
+        let limited = limit_search(&limits, 0);
+        assert_eq!(limited.depth, Some(1));
+        assert!(limited.nodes.is_some());
+        assert_eq!(limited.movetime, limits.movetime);
+    }
+
+    #[test]
+    fn picks_one_of_the_lines() {
+        let lines: Vec<PvLine> = [120, 100, 40, -300, -900]
+            .iter()
+            .map(|&score| PvLine { score, moves: Vec::new() })
+            .collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        for skill in 0..=MAX_SKILL {
+            for _ in 0..200 {
+                let picked = pick_line(&lines, skill, &mut rng);
+                assert!(picked < lines.len(), "level {} picked {}", skill, picked);
+                if skill == MAX_SKILL {
+                    assert_eq!(picked, 0);
+                }
+            }
+            assert_eq!(pick_line(&lines[..1], skill, &mut rng), 0);
+        }
+    }
+}
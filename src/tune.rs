@@ -0,0 +1,238 @@
+use crate::ai::quiet_positions;
+use crate::chessboard::Chessboard;
+use crate::eval::evaluate_explained;
+use crate::eval_params::{self, EvalParams};
+use rayon::prelude::*;
+use std::fs;
+use std::time::Instant;
+
+/// The first amount each parameter is nudged by. Once nudging by this much doesn't help any more,
+/// it's halved, until even nudging by 1 doesn't help.
+const INITIAL_STEP: i32 = 8;
+
+/// How many times the range the scaling constant `k` could be in is narrowed down
+const K_ITERATIONS: usize = 50;
+
+/// How many positions each thread takes at a time when working out the quiet positions. Each
+/// batch shares a searcher, and there are still plenty of batches to go round the threads.
+const QUIET_BATCH: usize = 256;
+
+/// A position from a game, and how that game ended
+struct Position {
+    chessboard: Chessboard,
+    /// 1 if White won, 0.5 for a draw, and 0 if Black won
+    result: f64,
+}
+
+/// Tunes the evaluation parameters with Texel's method: the evaluation of each position is turned
+/// into a predicted result with a sigmoid, and each parameter is nudged up and down for as long as
+/// that brings the predictions closer to the results that were actually played out.
+///
+/// Each line of the `path` file is a FEN followed by the result of the game, like `1-0`, `1/2-1/2`
+/// or `0.5` (optionally in quotes or brackets, or followed by a semicolon, as in EPD files). The
+/// parameters from `--params` (or the defaults) are the starting point, and the tuned ones are
+/// written to `output` after every pass, so tuning can be stopped at any time.
+pub fn run(path: &str, output: &str) -> Result<(), String> {
+    let start = Instant::now();
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let games = parse_positions(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let mut params = eval_params::params().clone();
+
+    // The evaluation can't see captures coming, so every position is evaluated at the end of its
+    // quiescence search instead. Working that out once, rather than for every set of parameters,
+    // makes tuning a lot quicker.
+    let positions: Vec<Position> = games
+        .par_chunks(QUIET_BATCH)
+        .flat_map(|batch| {
+            let quiet = quiet_positions(batch.iter().map(|(chessboard, _)| chessboard), &params);
+            quiet
+                .into_iter()
+                .zip(batch)
+                .map(|(chessboard, &(_, result))| Position { chessboard, result })
+                .collect::<Vec<_>>()
+        })
+        // checkmates and stalemates don't have a meaningful static evaluation
+        .filter(|position| {
+            let chessboard = &position.chessboard;
+            !chessboard.possible_moves(chessboard.turn).is_empty()
+        })
+        .collect();
+    if positions.is_empty() {
+        return Err(format!("{}: there are no positions to tune with", path));
+    }
+    println!(
+        "Found {} quiet positions in {} ms",
+        positions.len(),
+        start.elapsed().as_millis()
+    );
+
+    let k = fit_k(&positions, &params);
+    let initial_error = error(&positions, &params, k);
+    println!("Scaling constant k = {:.4}, error {:.6}", k, initial_error);
+
+    let mut values = flatten(&mut params);
+    let mut best_error = initial_error;
+    let mut step = INITIAL_STEP;
+    let mut pass = 1;
+    while step > 0 {
+        let mut changed = 0;
+        for i in 0..values.len() {
+            for delta in [step, -step] {
+                values[i] += delta;
+                unflatten(&mut params, &values);
+                let new_error = error(&positions, &params, k);
+                if new_error < best_error {
+                    best_error = new_error;
+                    changed += 1;
+                    break;
+                }
+                values[i] -= delta;
+            }
+        }
+        unflatten(&mut params, &values);
+
+        println!(
+            "Pass {} (step {}): error {:.6}, {} parameters changed, {} s",
+            pass,
+            step,
+            best_error,
+            changed,
+            start.elapsed().as_secs()
+        );
+        let header = format!(
+            "# Tuned on {} positions from {}: error {:.6}, down from {:.6}\n",
+            positions.len(),
+            path,
+            best_error,
+            initial_error
+        );
+        fs::write(output, header + &params.to_string()).map_err(|e| format!("{}: {}", output, e))?;
+
+        if changed == 0 {
+            step /= 2;
+        }
+        pass += 1;
+    }
+
+    println!(
+        "Error went from {:.6} to {:.6}. Wrote the tuned parameters to {}",
+        initial_error, best_error, output
+    );
+    Ok(())
+}
+
+/// The mean squared difference between the results predicted by the evaluation and the real ones
+fn error(positions: &[Position], params: &EvalParams, k: f64) -> f64 {
+    let total: f64 = positions
+        .par_iter()
+        .map(|position| {
+            // not `heuristic_score`: its pawn table would remember scores from other parameters
+            let score = evaluate_explained(&position.chessboard, params).score();
+            (position.result - predicted_result(score, k)).powi(2)
+        })
+        .sum();
+    total / positions.len() as f64
+}
+
+/// How likely White is to win (counting a draw as half), given the evaluation in centipawns
+fn predicted_result(score: i32, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score as f64 / 400.0))
+}
+
+/// Finds the scaling constant `k` that makes the current parameters predict the results best, so
+/// the tuning only has to improve the evaluation, not change its scale. The error only has one
+/// minimum, so this keeps cutting off the third of the range that it can't be in.
+fn fit_k(positions: &[Position], params: &EvalParams) -> f64 {
+    let (mut low, mut high) = (0.0, 4.0);
+    for _ in 0..K_ITERATIONS {
+        let third = (high - low) / 3.0;
+        if error(positions, params, low + third) < error(positions, params, high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Every parameter, one after another, in the order `visit_mut` visits them
+fn flatten(params: &mut EvalParams) -> Vec<i32> {
+    let mut values = Vec::new();
+    params.visit_mut(&mut |_, param| values.extend_from_slice(param));
+    values
+}
+
+/// Undoes `flatten`
+fn unflatten(params: &mut EvalParams, mut values: &[i32]) {
+    params.visit_mut(&mut |_, param| {
+        let (head, tail) = values.split_at(param.len());
+        param.copy_from_slice(head);
+        values = tail;
+    });
+}
+
+fn parse_positions(text: &str) -> Result<Vec<(Chessboard, f64)>, String> {
+    let mut positions = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        // The result comes last. Anything between it and the first four fields of the FEN (the
+        // clocks, or EPD operations) is ignored, since the evaluation doesn't use it.
+        let result = match (tokens.len(), tokens.last().and_then(|t| parse_result(t))) {
+            (5.., Some(result)) => result,
+            _ => return Err(format!("line {} isn't a FEN followed by a result", i + 1)),
+        };
+        let chessboard = Chessboard::try_from_fen(&tokens[..4].join(" "))
+            .map_err(|e| format!("line {}: invalid FEN ({})", i + 1, e))?;
+        positions.push((chessboard, result));
+    }
+    Ok(positions)
+}
+
+/// Reads a game result from White's point of view
+fn parse_result(token: &str) -> Option<f64> {
+    match token.trim_matches(|c| "[]\";".contains(c)) {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_results() {
+        for (token, result) in [("1-0", 1.0), ("\"1/2-1/2\";", 0.5), ("[0-1]", 0.0), ("0.5", 0.5)] {
+            assert_eq!(parse_result(token), Some(result), "{}", token);
+        }
+        assert_eq!(parse_result("2-0"), None);
+    }
+
+    #[test]
+    fn parses_positions() {
+        let text = "# a comment\n\
+                    rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1 1/2-1/2\n\
+                    \n\
+                    6k1/5ppp/8/8/8/8/8/R5K1 w - - c9 \"1-0\";\n";
+        let positions = parse_positions(text).unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].1, 0.5);
+        assert_eq!(positions[1].1, 1.0);
+        assert_eq!(positions[1].0.turn, crate::piece::Side::Light);
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let good = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 1-0";
+        for bad in ["6k1/5ppp/8/8/8/8/R5K1 w - - 1-0", "6k1/5ppp/8/8/8/8/8/R5K1 w - - 3-0", "1-0"] {
+            let text = format!("{}\n{}\n", good, bad);
+            let error = parse_positions(&text).err().unwrap();
+            assert!(error.starts_with("line 2"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::ai::{self, GameClock, SearchLimits, SearchOptions, SearchReport};
+use crate::chessboard::{Chessboard, STARTPOS};
+use crate::eval::evaluate_explained;
+use crate::eval_params;
+use crate::nnue::{self, Network};
+use crate::piece::{Piece, PieceData, Side};
+use crate::skill;
+use crate::table::{TranspositionTable, DEFAULT_TT_MEGABYTES};
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The largest `Hash` option we accept, in megabytes
+const MAX_TT_MEGABYTES: usize = 65536;
+/// The largest `Threads` option we accept
+const MAX_THREADS: usize = 256;
+/// The largest `MultiPV` option we accept. No position has more legal moves than this.
+const MAX_MULTI_PV: usize = 256;
+
+/// Runs the AI as a Universal Chess Interface engine over stdin and stdout, so it can be used from
+/// other chess GUIs, and so its options can be set at runtime.
+pub fn run() {
+    let mut uci = Uci::new();
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"uci") => {
+                println!("id name Chess");
+                println!("id author Ritoban Roy-Chowdhury");
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_TT_MEGABYTES, MAX_TT_MEGABYTES
+                );
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+                println!(
+                    "option name Skill Level type spin default {0} min 0 max {0}",
+                    skill::MAX_SKILL
+                );
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {1} min {0} max {1}",
+                    skill::MIN_ELO,
+                    skill::MAX_ELO
+                );
+                println!("option name UseNNUE type check default true");
+                println!("option name EvalFile type string default <empty>");
+                println!("uciok");
+            }
+            Some(&"isready") => println!("readyok"),
+            Some(&"setoption") => uci.set_option(&tokens[1..]),
+            Some(&"ucinewgame") => {
+                uci.wait();
+                uci.tt.clear();
+            }
+            Some(&"position") => uci.position(&tokens[1..]),
+            Some(&"go") => uci.go(&tokens[1..]),
+            Some(&"stop") => uci.wait(),
+            // not part of UCI, but handy for seeing what the evaluation thinks of a position
+            Some(&"eval") => println!("{}", evaluate_explained(&uci.chessboard, eval_params::params())),
+            Some(&"quit") => {
+                uci.wait();
+                break;
+            }
+            _ => {}
+        }
+    }
+    // the input ran out (or broke), but a search that's underway still gets to print its move
+    uci.finish();
+}
+
+struct Uci {
+    chessboard: Chessboard,
+    tt: Arc<TranspositionTable>,
+    options: SearchOptions,
+    /// The `Skill Level` option. `UCI_Elo` takes its place while `UCI_LimitStrength` is on.
+    skill_level: u8,
+    limit_strength: bool,
+    elo: u32,
+    stop: Arc<AtomicBool>,
+    search: Option<thread::JoinHandle<()>>,
+    /// Whether the search that's running was started with `go infinite`, so it only ends when
+    /// it's stopped
+    infinite: bool,
+}
+
+impl Uci {
+    fn new() -> Uci {
+        Uci {
+            chessboard: Chessboard::from_fen(String::from(STARTPOS)),
+            tt: Arc::new(TranspositionTable::new(DEFAULT_TT_MEGABYTES)),
+            options: SearchOptions::default(),
+            skill_level: skill::MAX_SKILL,
+            limit_strength: false,
+            elo: skill::MAX_ELO,
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+            infinite: false,
+        }
+    }
+
+    /// Stops the search that's running (if there is one), and waits for it to print its move.
+    fn wait(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join();
+    }
+
+    /// Lets the search that's running (if there is one) finish, and waits for it to print its
+    /// move. An infinite search never finishes on its own, so that one is stopped.
+    fn finish(&mut self) {
+        if self.infinite {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        self.join();
+    }
+
+    fn join(&mut self) {
+        if let Some(search) = self.search.take() {
+            // the GUI is still waiting for a move, even if the search crashed before printing one
+            if search.join().is_err() {
+                println!("info string the search crashed");
+                println!("bestmove 0000");
+            }
+        }
+    }
+
+    /// `setoption name <name> value <value>`
+    fn set_option(&mut self, tokens: &[&str]) {
+        let value_idx = tokens.iter().position(|t| *t == "value");
+        let name = tokens[1..value_idx.unwrap_or(tokens.len())].join(" ");
+        let value = value_idx.map(|i| tokens[i + 1..].join(" "));
+
+        match (name.to_lowercase().as_str(), value) {
+            ("hash", Some(value)) => {
+                if let Ok(megabytes) = value.parse::<usize>() {
+                    self.wait();
+                    let megabytes = megabytes.clamp(1, MAX_TT_MEGABYTES);
+                    // Nothing else should hold on to the table once the search is over, but if
+                    // something does, it can keep the old one
+                    match Arc::get_mut(&mut self.tt) {
+                        Some(tt) => tt.resize(megabytes),
+                        None => self.tt = Arc::new(TranspositionTable::new(megabytes)),
+                    }
+                }
+            }
+            ("threads", Some(value)) => {
+                if let Ok(threads) = value.parse::<usize>() {
+                    self.options.threads = threads.clamp(1, MAX_THREADS);
+                }
+            }
+            ("multipv", Some(value)) => {
+                if let Ok(multi_pv) = value.parse::<usize>() {
+                    self.options.multi_pv = multi_pv.clamp(1, MAX_MULTI_PV);
+                }
+            }
+            ("skill level", Some(value)) => {
+                if let Ok(level) = value.parse::<u8>() {
+                    self.skill_level = level.min(skill::MAX_SKILL);
+                }
+            }
+            ("uci_limitstrength", Some(value)) => self.limit_strength = value == "true",
+            ("uci_elo", Some(value)) => {
+                if let Ok(elo) = value.parse::<u32>() {
+                    self.elo = elo;
+                }
+            }
+            ("usennue", Some(value)) => self.options.use_nnue = value == "true",
+            ("evalfile", Some(path)) => match Network::load(&path).map(nnue::set_network) {
+                Ok(true) => println!("info string loaded the network from {}", path),
+                Ok(false) => println!("info string a network is already loaded"),
+                Err(e) => println!("info string couldn't load the network: {}", e),
+            },
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    /// `position [startpos | fen <fen>] [moves <move>...]`
+    fn position(&mut self, tokens: &[&str]) {
+        self.wait();
+        let moves_idx = tokens.iter().position(|t| *t == "moves");
+        let setup = &tokens[..moves_idx.unwrap_or(tokens.len())];
+
+        // the position only changes once all of it has been read, so a mistake leaves the last
+        // one in place
+        let mut chessboard = match setup {
+            ["startpos"] => Chessboard::from_fen(String::from(STARTPOS)),
+            ["fen", fen @ ..] => match Chessboard::try_from_fen(&fen.join(" ")) {
+                Ok(chessboard) => chessboard,
+                Err(e) => {
+                    println!("info string invalid fen {}: {}", fen.join(" "), e);
+                    return;
+                }
+            },
+            _ => {
+                println!("info string invalid position command");
+                return;
+            }
+        };
+
+        for m in moves_idx.map_or(&[][..], |i| &tokens[i + 1..]) {
+            if !make_uci_move(&mut chessboard, m) {
+                println!("info string illegal move {}", m);
+                return;
+            }
+        }
+        self.chessboard = chessboard;
+    }
+
+    /// `go [depth <d>] [nodes <n>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>]
+    /// [binc <ms>] [movestogo <n>] [infinite]`
+    fn go(&mut self, tokens: &[&str]) {
+        self.wait();
+
+        let mut limits = SearchLimits::default();
+        let mut time = [None, None];
+        let mut increment = [Duration::default(); 2];
+        let mut moves_to_go = None;
+        let mut infinite = false;
+
+        let mut iter = tokens.iter();
+        while let Some(token) = iter.next() {
+            let mut value = || iter.next().and_then(|v| v.parse::<u64>().ok());
+            match *token {
+                "depth" => limits.depth = value().map(|d| d.min(u64::from(u8::MAX)) as u8),
+                "nodes" => limits.nodes = value(),
+                "movetime" => limits.movetime = value().map(Duration::from_millis),
+                "wtime" => time[0] = value().map(Duration::from_millis),
+                "btime" => time[1] = value().map(Duration::from_millis),
+                "winc" => increment[0] = value().map(Duration::from_millis).unwrap_or_default(),
+                "binc" => increment[1] = value().map(Duration::from_millis).unwrap_or_default(),
+                "movestogo" => moves_to_go = value().map(|m| m as u32),
+                "infinite" => infinite = true,
+                _ => {}
+            }
+        }
+
+        let us = match self.chessboard.turn {
+            Side::Light => 0,
+            Side::Dark => 1,
+        };
+        // an infinite search ignores the clock, and carries on until it's told to stop
+        if infinite {
+            limits.movetime = None;
+            time[us] = None;
+        }
+        limits.clock = time[us].map(|time_left| GameClock {
+            time_left,
+            increment: increment[us],
+            moves_to_go,
+        });
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let chessboard = self.chessboard.clone();
+        let tt = Arc::clone(&self.tt);
+        let stop = Arc::clone(&self.stop);
+        self.infinite = infinite;
+        let mut options = self.options;
+        options.skill_level = if self.limit_strength {
+            skill::skill_for_elo(self.elo)
+        } else {
+            self.skill_level
+        };
+
+        self.search = Some(thread::spawn(move || {
+            let result = ai::get_best_move(
+                &chessboard,
+                &limits,
+                &options,
+                tt,
+                Arc::clone(&stop),
+                &mut print_info,
+            );
+            // The search can run out of things to search (like when it finds a mate), but the
+            // GUI mustn't get a move from an infinite search until it asks for one
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            match result.best_move {
+                Some(best_move) => println!("bestmove {}", uci_move(best_move)),
+                // the protocol's null move, for when we're checkmated or stalemated
+                None => println!("bestmove 0000"),
+            }
+        }));
+    }
+}
+
+/// Sends what the search has found so far to the GUI
+fn print_info(report: &SearchReport) {
+    let pv: Vec<String> = report.pv.iter().map(|m| m.to_string()).collect();
+    println!(
+        "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} tbhits {} \
+         time {} pv {}",
+        report.depth,
+        report.seldepth,
+        report.multipv,
+        uci_score(report.score),
+        report.nodes,
+        report.nps,
+        report.hashfull,
+        report.tbhits,
+        report.time.as_millis(),
+        pv.join(" ")
+    );
+}
+
+/// Formats a score as `mate <moves>` (negative if we're getting mated), or `cp <centipawns>`.
+fn uci_score(score: i32) -> String {
+    match ai::mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+/// Formats a move like `e2e4`, or `e7e8q` for a promotion (the AI always promotes to a queen).
+pub fn uci_move(m: (&Piece, [u8; 2])) -> String {
+    let (piece, end_pos) = m;
+    let start_pos = piece.data().position;
+    let promotion = match piece {
+        Piece::Pawn(data) if end_pos[1] == data.side.other().back_rank() => "q",
+        _ => "",
+    };
+    format!("{}{}{}", square_name(start_pos), square_name(end_pos), promotion)
+}
+
+fn square_name(pos: [u8; 2]) -> String {
+    format!("{}{}", (pos[0] + b'a') as char, pos[1] + 1)
+}
+
+fn parse_square(s: &[u8]) -> Option<[u8; 2]> {
+    match s {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some([file - b'a', rank - b'1']),
+        _ => None,
+    }
+}
+
+/// Plays a move given like `e2e4` or `e7e8q`, and returns false if it was illegal.
+pub fn make_uci_move(chessboard: &mut Chessboard, m: &str) -> bool {
+    let bytes = m.as_bytes();
+    if bytes.len() < 4 {
+        return false;
+    }
+    let (start_pos, end_pos) = match (parse_square(&bytes[0..2]), parse_square(&bytes[2..4])) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return false,
+    };
+    let promotion: &dyn Fn(PieceData) -> Piece = match bytes.get(4) {
+        Some(b'r') => &Piece::Rook,
+        Some(b'b') => &Piece::Bishop,
+        Some(b'n') => &Piece::Knight,
+        _ => &Piece::Queen,
+    };
+
+    let piece = match chessboard.piece_at(start_pos) {
+        Some(piece) => piece.clone(),
+        None => return false,
+    };
+    chessboard.try_move(&piece, end_pos, Some(promotion)) != crate::chessboard::MoveResult::Invalid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_positions_keep_the_last_one() {
+        let mut uci = Uci::new();
+        uci.position(&["startpos", "moves", "e2e4"]);
+        let after_e4 = uci.chessboard.hash();
+
+        uci.position(&["fen", "8/8/8/8/8/8/8/8", "w", "-", "-", "0", "1"]);
+        assert_eq!(uci.chessboard.hash(), after_e4);
+        // the first two moves are fine, but the whole command is thrown away
+        uci.position(&["startpos", "moves", "d2d4", "d7d5", "d4d6"]);
+        assert_eq!(uci.chessboard.hash(), after_e4);
+    }
+}
@@ -0,0 +1,39 @@
+// These set the global network, which would change how the AI evaluates positions in every other
+// test, so they get a process of their own.
+
+use chess::chessboard::Chessboard;
+use chess::nnue::{self, Accumulator, Network, HIDDEN};
+use chess::uci::make_uci_move;
+use std::fs;
+
+/// A network file with made-up, but different, weights for every input
+fn network_bytes() -> Vec<u8> {
+    let mut bytes = b"CHESSNN1".to_vec();
+    bytes.extend_from_slice(&(HIDDEN as u32).to_le_bytes());
+    let weights = 768 * HIDDEN + 3 * HIDDEN;
+    for i in 0..weights {
+        let weight = (i * 7919 % 201) as i16 - 100;
+        bytes.extend_from_slice(&weight.to_le_bytes());
+    }
+    bytes.extend_from_slice(&1234i32.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn accumulator_is_updated_incrementally() {
+    let path = std::env::temp_dir().join(format!("test_network_{}.nn", std::process::id()));
+    fs::write(&path, network_bytes()).unwrap();
+    let network = Network::load(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+    assert!(nnue::set_network(network.unwrap()));
+    let network = nnue::network().unwrap();
+
+    // en passant, castling on both sides, a promotion that captures, and captures
+    let mut chessboard =
+        Chessboard::from_fen(String::from("r3k2r/1P4p1/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1"));
+    for m in ["e5d6", "e8g8", "b7a8q", "f8a8", "e1c1", "a8a1", "c1b2", "a1d1"].iter() {
+        assert!(make_uci_move(&mut chessboard, m), "{} should be legal", m);
+        let fresh = Accumulator::new(network, chessboard.pieces());
+        assert_eq!(chessboard.accumulator(), Some(&fresh), "after {}", m);
+    }
+}